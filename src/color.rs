@@ -36,6 +36,7 @@ pub const GREY_2: Color = Color::from_rgb(
     0xCC as f32 / 255.0,
     0xCC as f32 / 255.0,
 );
+#[allow(dead_code)]
 pub const GREY_1: Color = Color::from_rgb(
     0xE6 as f32 / 255.0,
     0xE6 as f32 / 255.0,
@@ -0,0 +1,294 @@
+use serde_derive::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    manager_api::{ManagerApi, ManagerApiSettings},
+    mock_device::MockDevice,
+    mock_hsm::HsmScript,
+    model::Model,
+    speculos::DEFAULT_APDU_ADDR,
+    transcript::{Recorder, ReplayTransport},
+};
+
+const USAGE: &str = "Usage: bacca [OPTIONS]
+
+Options:
+    --config <FILE>              Settings file (default: $XDG_CONFIG_HOME/bacca/config.json)
+    --speculos                   Use a Speculos emulator instead of a real device
+    --speculos-addr <ADDR>       Address of the Speculos APDU port (default: 127.0.0.1:9999)
+    --speculos-api <URL>         Url of the Speculos REST API, used to press buttons
+    --speculos-auto-approve      Press both buttons when the emulator waits for a confirmation
+    --record <FILE>              Record every APDU exchanged with the device to a JSON lines transcript
+    --replay <FILE>              Serve a recorded JSON transcript instead of talking to a device
+    --mock <MODEL>               Simulate a device in memory (nanos, nanos+, nanox, stax or flex)
+    --api-v1-url <URL>           Base url of Ledger's manager API v1
+    --api-v2-url <URL>           Base url of Ledger's manager API v2
+    --socket-url <URL>           Base url of Ledger's HSM scriptrunner websocket
+    --live-common-version <VER>  Ledger Live version announced to the manager API
+    --provider <ID>              Manager API provider id
+    --api-timeout <SECS>         Timeout of the requests to the manager API (default: 30)
+    --hsm-timeout <SECS>         Delay after which a silent HSM is given up (default: 30)
+    --mock-hsm <FILE>            Serve the scripted HSM session in <FILE> from a local websocket
+    -h, --help                   Print this help
+
+Environment:
+    BACCA_CONFIG, BACCA_SPECULOS_ADDR, BACCA_SPECULOS_API, BACCA_SPECULOS_AUTO_APPROVE,
+    BACCA_RECORD, BACCA_REPLAY, BACCA_MOCK, BACCA_API_V1_URL, BACCA_API_V2_URL,
+    BACCA_SOCKET_URL, BACCA_LIVE_COMMON_VERSION, BACCA_PROVIDER, BACCA_API_TIMEOUT,
+    BACCA_HSM_TIMEOUT, BACCA_MOCK_HSM
+
+Settings file:
+    A JSON object whose optional keys are api_v1_url, api_v2_url, socket_url,
+    live_common_version, provider, timeout_secs, hsm_timeout_secs, speculos_addr,
+    speculos_api and speculos_auto_approve. Setting speculos_addr selects the emulator.";
+
+/// Which backend is used to talk to the device.
+#[derive(Debug, Clone, Default)]
+pub enum TransportConfig {
+    #[default]
+    Hid,
+    Speculos {
+        apdu_addr: String,
+        api_url: Option<String>,
+        auto_approve: bool,
+    },
+    Replay(Arc<ReplayTransport>),
+    Mock(Arc<MockDevice>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub transport: TransportConfig,
+    /// If set, exchanges with the device are recorded there.
+    pub recorder: Option<Arc<Recorder>>,
+    pub manager_api: ManagerApi,
+    /// If set, a local HSM playing this script is started in place of Ledger's one.
+    pub mock_hsm: Option<HsmScript>,
+}
+
+impl Config {
+    /// Build the configuration from the settings file, then the environment, then the command
+    /// line arguments, each one taking precedence over the previous one.
+    pub fn load() -> Result<Self, String> {
+        let args: Vec<String> = env::args().skip(1).collect();
+
+        let mut manager_api = ManagerApi::default();
+        let settings_path = flag_value(&args, "--config")?
+            .or_else(|| env::var("BACCA_CONFIG").ok())
+            .map(PathBuf::from)
+            .or_else(|| default_settings_path().filter(|p| p.exists()));
+        let settings = match settings_path {
+            Some(path) => load_settings(&path)?,
+            None => Settings::default(),
+        };
+        manager_api.apply(settings.manager_api);
+        manager_api.apply(ManagerApiSettings {
+            api_v1_url: env::var("BACCA_API_V1_URL").ok(),
+            api_v2_url: env::var("BACCA_API_V2_URL").ok(),
+            socket_url: env::var("BACCA_SOCKET_URL").ok(),
+            live_common_version: env::var("BACCA_LIVE_COMMON_VERSION").ok(),
+            provider: env::var("BACCA_PROVIDER")
+                .ok()
+                .map(|p| parse_provider(&p))
+                .transpose()?,
+            timeout_secs: env::var("BACCA_API_TIMEOUT")
+                .ok()
+                .map(|t| parse_timeout(&t))
+                .transpose()?,
+            hsm_timeout_secs: env::var("BACCA_HSM_TIMEOUT")
+                .ok()
+                .map(|t| parse_timeout(&t))
+                .transpose()?,
+        });
+
+        let mut speculos_addr = env::var("BACCA_SPECULOS_ADDR")
+            .ok()
+            .or(settings.speculos.speculos_addr);
+        let mut speculos = speculos_addr.is_some();
+        let mut api_url = env::var("BACCA_SPECULOS_API")
+            .ok()
+            .or(settings.speculos.speculos_api);
+        let mut auto_approve = env::var("BACCA_SPECULOS_AUTO_APPROVE").is_ok()
+            || settings.speculos.speculos_auto_approve.unwrap_or(false);
+        let mut record = env::var("BACCA_RECORD").ok().map(PathBuf::from);
+        let mut replay = env::var("BACCA_REPLAY").ok().map(PathBuf::from);
+        let mut mock = env::var("BACCA_MOCK").ok();
+        let mut mock_hsm = env::var("BACCA_MOCK_HSM").ok().map(PathBuf::from);
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                // Already handled above.
+                "--config" => {
+                    next_value(&mut args, &arg)?;
+                }
+                "--speculos" => speculos = true,
+                "--speculos-addr" => {
+                    speculos = true;
+                    speculos_addr = Some(next_value(&mut args, &arg)?);
+                }
+                "--speculos-api" => api_url = Some(next_value(&mut args, &arg)?),
+                "--speculos-auto-approve" => auto_approve = true,
+                "--record" => record = Some(next_value(&mut args, &arg)?.into()),
+                "--replay" => replay = Some(next_value(&mut args, &arg)?.into()),
+                "--mock" => mock = Some(next_value(&mut args, &arg)?),
+                "--api-v1-url" => manager_api.api_v1_url = next_value(&mut args, &arg)?,
+                "--api-v2-url" => manager_api.api_v2_url = next_value(&mut args, &arg)?,
+                "--socket-url" => manager_api.socket_url = next_value(&mut args, &arg)?,
+                "--live-common-version" => {
+                    manager_api.live_common_version = next_value(&mut args, &arg)?
+                }
+                "--provider" => {
+                    manager_api.provider = parse_provider(&next_value(&mut args, &arg)?)?
+                }
+                "--api-timeout" => {
+                    manager_api.timeout =
+                        Duration::from_secs(parse_timeout(&next_value(&mut args, &arg)?)?)
+                }
+                "--hsm-timeout" => {
+                    manager_api.hsm_timeout =
+                        Duration::from_secs(parse_timeout(&next_value(&mut args, &arg)?)?)
+                }
+                "--mock-hsm" => mock_hsm = Some(next_value(&mut args, &arg)?.into()),
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ => return Err(format!("Unknown argument: {}\n\n{}", arg, USAGE)),
+            }
+        }
+
+        let transport = if let Some(path) = replay {
+            let replay = ReplayTransport::from_file(&path)
+                .map_err(|e| format!("Fail to load transcript {}: {}", path.display(), e))?;
+            TransportConfig::Replay(Arc::new(replay))
+        } else if let Some(model) = mock {
+            let model = Model::from_name(&model)
+                .ok_or_else(|| format!("Unknown device model: {}\n\n{}", model, USAGE))?;
+            TransportConfig::Mock(Arc::new(MockDevice::new(model)))
+        } else if speculos {
+            TransportConfig::Speculos {
+                apdu_addr: speculos_addr.unwrap_or_else(|| DEFAULT_APDU_ADDR.to_string()),
+                api_url,
+                auto_approve,
+            }
+        } else {
+            TransportConfig::Hid
+        };
+
+        Ok(Config {
+            transport,
+            recorder: record
+                .map(|path| {
+                    Recorder::new(&path)
+                        .map(Arc::new)
+                        .map_err(|e| format!("Fail to create transcript {}: {}", path.display(), e))
+                })
+                .transpose()?,
+            manager_api,
+            mock_hsm: mock_hsm
+                .map(|path| HsmScript::from_file(&path))
+                .transpose()?,
+        })
+    }
+}
+
+fn default_settings_path() -> Option<PathBuf> {
+    let config_dir = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| Path::new(&home).join(".config")))
+        .ok()?;
+    Some(config_dir.join("bacca").join("config.json"))
+}
+
+/// The content of the settings file.
+#[derive(Debug, Default)]
+struct Settings {
+    manager_api: ManagerApiSettings,
+    speculos: SpeculosSettings,
+}
+
+/// The keys of the settings file about the Speculos emulator.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpeculosSettings {
+    speculos_addr: Option<String>,
+    speculos_api: Option<String>,
+    speculos_auto_approve: Option<bool>,
+}
+
+fn load_settings(path: &Path) -> Result<Settings, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Fail to read settings file {}: {}", path.display(), e))?;
+    parse_settings(&content).map_err(|e| format!("Invalid settings file {}: {}", path.display(), e))
+}
+
+/// Split the settings between the Speculos keys and the manager API ones, each set rejecting the
+/// keys it doesn't know.
+fn parse_settings(content: &str) -> Result<Settings, serde_json::Error> {
+    let (speculos, manager_api): (serde_json::Map<_, _>, _) =
+        serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(content)?
+            .into_iter()
+            .partition(|(key, _)| key.starts_with("speculos_"));
+    Ok(Settings {
+        manager_api: serde_json::from_value(manager_api.into())?,
+        speculos: serde_json::from_value(speculos.into())?,
+    })
+}
+
+fn parse_provider(provider: &str) -> Result<u32, String> {
+    provider
+        .parse()
+        .map_err(|_| format!("Invalid provider id: {}", provider))
+}
+
+fn parse_timeout(secs: &str) -> Result<u64, String> {
+    secs.parse()
+        .map_err(|_| format!("Invalid timeout: {}", secs))
+}
+
+/// Look up the value of a flag before the full parsing of the arguments.
+fn flag_value(args: &[String], flag: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => args
+            .get(i + 1)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| format!("Missing value for {}\n\n{}", flag, USAGE)),
+        None => Ok(None),
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for {}\n\n{}", flag, USAGE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_file() {
+        let settings = parse_settings(
+            r#"{"socket_url": "ws://127.0.0.1:8080", "speculos_addr": "127.0.0.1:40000",
+                "speculos_auto_approve": true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.manager_api.socket_url.as_deref(),
+            Some("ws://127.0.0.1:8080")
+        );
+        assert_eq!(
+            settings.speculos.speculos_addr.as_deref(),
+            Some("127.0.0.1:40000")
+        );
+        assert_eq!(settings.speculos.speculos_auto_approve, Some(true));
+        assert!(settings.speculos.speculos_api.is_none());
+
+        assert!(parse_settings(r#"{"speculos_port": 40000}"#).is_err());
+        assert!(parse_settings(r#"{"socket": "ws://127.0.0.1:8080"}"#).is_err());
+    }
+}
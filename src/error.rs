@@ -0,0 +1,231 @@
+use std::{array::TryFromSliceError, error, fmt, str::Utf8Error};
+
+use crate::{
+    ledger_lib::{ParseError, StatusCode},
+    manager_api::ManagerApiError,
+    transport::{HidErrorKind, TransportError},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LedgerError {
+    /// The HID layer could not be initialized or failed to talk to the device.
+    Hid(String),
+    /// The OS denied access to the device.
+    PermissionDenied(String),
+    /// No device is connected.
+    NoDevice,
+    /// The exchange with the device failed below the APDU level.
+    Transport(TransportError),
+    /// The device is locked, the user must enter its PIN. Holds the status word.
+    DeviceLocked(u16),
+    /// The user refused the action on the device. Holds the status word.
+    UserRefused(u16),
+    /// The device has not been set up yet. Holds the status word, none if the GET_VERSION flags
+    /// told.
+    NotOnboarded(Option<u16>),
+    /// The device has not enough free storage.
+    NotEnoughSpace(u16),
+    /// The app doesn't fit in the free storage, the bytes needed then the bytes free.
+    InsufficientStorage(u32, u32),
+    /// An app is running, the device must be on its dashboard.
+    AppOpen(String),
+    /// The manager has no app for this firmware.
+    UnsupportedFirmware(String),
+    /// The app is on the device already, it can only be updated.
+    AppAlreadyInstalled(String),
+    /// The device answered with an unexpected status word.
+    Device(u16),
+    /// Ledger's HSM reported an error, or the session with it went wrong.
+    Hsm(String),
+    /// Ledger's servers could not be reached.
+    Network(String),
+    /// An answer (from the device or the manager API) could not be parsed.
+    Parse(String),
+    /// The manager API has no build of the app for this device.
+    AppNotAvailable(String),
+    /// The app this one depends on failed to install.
+    MissingDependency(String),
+    /// Installed apps depend on this one, they must be removed first.
+    RequiredBy(Vec<String>),
+    /// The user cancelled the operation.
+    Cancelled,
+}
+
+impl LedgerError {
+    /// Interpret a status word returned by the device, `None` meaning success.
+    pub fn from_status(status: u16) -> Option<Self> {
+        match StatusCode::from_u16(status) {
+            Some(StatusCode::OK) => None,
+            Some(StatusCode::LOCKED_DEVICE) => Some(LedgerError::DeviceLocked(status)),
+            Some(StatusCode::USER_REFUSED_ON_DEVICE)
+            | Some(StatusCode::CONDITIONS_OF_USE_NOT_SATISFIED) => {
+                Some(LedgerError::UserRefused(status))
+            }
+            Some(StatusCode::DEVICE_NOT_ONBOARDED) | Some(StatusCode::DEVICE_NOT_ONBOARDED_2) => {
+                Some(LedgerError::NotOnboarded(Some(status)))
+            }
+            Some(StatusCode::NOT_ENOUGH_SPACE) | Some(StatusCode::NOT_ENOUGH_MEMORY_SPACE) => {
+                Some(LedgerError::NotEnoughSpace(status))
+            }
+            _ => Some(LedgerError::Device(status)),
+        }
+    }
+
+    /// The raw status word returned by the device, if any.
+    #[allow(unused)]
+    pub fn status_word(&self) -> Option<u16> {
+        match self {
+            LedgerError::DeviceLocked(s)
+            | LedgerError::UserRefused(s)
+            | LedgerError::NotEnoughSpace(s)
+            | LedgerError::Device(s) => Some(*s),
+            LedgerError::NotOnboarded(s) => *s,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::Hid(e) => write!(f, "HID error: {}", e),
+            LedgerError::PermissionDenied(e) => write!(
+                f,
+                "Permission denied when accessing the device ({}). On Linux, make sure Ledger's udev rules are installed.",
+                e
+            ),
+            LedgerError::NoDevice => write!(f, "No device connected."),
+            LedgerError::Transport(e) => write!(f, "Fail to communicate with the device: {}", e),
+            LedgerError::DeviceLocked(_) => write!(f, "The device is locked, please unlock it."),
+            LedgerError::UserRefused(_) => write!(f, "The action was refused on the device."),
+            LedgerError::NotOnboarded(_) => write!(
+                f,
+                "The device is not set up, please set it up before installing apps."
+            ),
+            LedgerError::NotEnoughSpace(_) => write!(
+                f,
+                "Not enough space left on the device, uninstall some apps to free space."
+            ),
+            LedgerError::InsufficientStorage(needed, free) => write!(
+                f,
+                "The app needs {} KB but only {} KB are free, uninstall some apps to free space.",
+                needed.div_ceil(1024),
+                free / 1024
+            ),
+            LedgerError::AppOpen(app) => write!(
+                f,
+                "The {} app is open, please close it to go back to the dashboard.",
+                app
+            ),
+            LedgerError::UnsupportedFirmware(version) => write!(
+                f,
+                "Apps can't be installed on firmware {}, please update the device with Ledger Live.",
+                version
+            ),
+            LedgerError::AppAlreadyInstalled(app) => {
+                write!(f, "{} is already installed, update it instead.", app)
+            }
+            LedgerError::Device(s) => write!(f, "{}", StatusCode::describe(*s)),
+            LedgerError::Hsm(e) => write!(f, "Ledger's HSM error: {}", e),
+            LedgerError::Network(e) => write!(f, "Network error: {}", e),
+            LedgerError::Parse(e) => write!(f, "Invalid data: {}", e),
+            LedgerError::AppNotAvailable(app) => {
+                write!(f, "{} is not available for this device.", app)
+            }
+            LedgerError::MissingDependency(app) => {
+                write!(f, "It depends on the {} app, which failed to install.", app)
+            }
+            LedgerError::RequiredBy(apps) => write!(
+                f,
+                "{} {} on this app, uninstall {} first.",
+                apps.join(", "),
+                if apps.len() > 1 { "depend" } else { "depends" },
+                if apps.len() > 1 { "them" } else { "it" }
+            ),
+            LedgerError::Cancelled => write!(f, "Operation cancelled."),
+        }
+    }
+}
+
+impl error::Error for LedgerError {}
+
+impl From<TransportError> for LedgerError {
+    fn from(e: TransportError) -> Self {
+        match e {
+            TransportError::Hid(HidErrorKind::PermissionDenied, e) => {
+                LedgerError::PermissionDenied(e)
+            }
+            TransportError::Hid(_, e) => LedgerError::Hid(e),
+            e => LedgerError::Transport(e),
+        }
+    }
+}
+
+impl From<ManagerApiError> for LedgerError {
+    fn from(e: ManagerApiError) -> Self {
+        match e {
+            ManagerApiError::Json(e) => LedgerError::Parse(e),
+            e => LedgerError::Network(e.to_string()),
+        }
+    }
+}
+
+impl From<ParseError> for LedgerError {
+    fn from(e: ParseError) -> Self {
+        LedgerError::Parse(e.to_string())
+    }
+}
+
+impl From<Utf8Error> for LedgerError {
+    fn from(e: Utf8Error) -> Self {
+        LedgerError::Parse(e.to_string())
+    }
+}
+
+impl From<TryFromSliceError> for LedgerError {
+    fn from(e: TryFromSliceError) -> Self {
+        LedgerError::Parse(e.to_string())
+    }
+}
+
+impl From<hex::FromHexError> for LedgerError {
+    fn from(e: hex::FromHexError) -> Self {
+        LedgerError::Parse(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for LedgerError {
+    fn from(e: serde_json::Error) -> Self {
+        LedgerError::Parse(e.to_string())
+    }
+}
+
+impl From<tungstenite::Error> for LedgerError {
+    fn from(e: tungstenite::Error) -> Self {
+        LedgerError::Network(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_word() {
+        for (status, error) in [
+            (0x5515, LedgerError::DeviceLocked(0x5515)),
+            (0x5501, LedgerError::UserRefused(0x5501)),
+            (0x6985, LedgerError::UserRefused(0x6985)),
+            (0x6611, LedgerError::NotOnboarded(Some(0x6611))),
+            (0x6d07, LedgerError::NotOnboarded(Some(0x6d07))),
+            (0x5102, LedgerError::NotEnoughSpace(0x5102)),
+            (0x6f00, LedgerError::Device(0x6f00)),
+        ] {
+            let e = LedgerError::from_status(status).unwrap();
+            assert_eq!(e, error);
+            assert_eq!(e.status_word(), Some(status));
+        }
+        assert_eq!(LedgerError::from_status(0x9000), None);
+        assert_eq!(LedgerError::NotOnboarded(None).status_word(), None);
+    }
+}
@@ -1,15 +1,22 @@
 use async_channel::{Receiver, Sender};
 use iced::{
     alignment::Horizontal,
-    executor,
-    widget::{container, Button, Column, Row, Space, Text},
-    Application, Element, Length, Renderer,
+    event, executor,
+    widget::{container, pick_list, scrollable, Button, Column, ProgressBar, Row, Space, Text},
+    window, Application, Element, Event, Length, Renderer,
 };
 use iced_runtime::{futures::Subscription, Command};
+use std::collections::HashMap;
 
 use crate::{
-    ledger::{LedgerListener, LedgerMessage, Version},
-    theme::Theme,
+    error::LedgerError,
+    hotplug::LedgerDevice,
+    ledger::{
+        DeviceState, InstallOutcome, LedgerListener, LedgerMessage, UninstallOutcome, Version,
+    },
+    ledger_lib::{HsmPhase, HsmProgress},
+    manager_api::{AppId, CatalogApp},
+    theme::{self, Theme},
 };
 
 #[derive(Debug)]
@@ -22,12 +29,25 @@ pub struct Flags {
 pub enum Message {
     LedgerClientMsg(LedgerMessage),
 
-    UpdateMain,
-    InstallMain,
-    UpdateTest,
-    InstallTest,
-    #[allow(unused)]
+    Install(AppId),
+    Update(AppId),
+    /// Pick an app of the catalog to install.
+    SelectApp(AppId),
     Connect,
+    /// Stop the running operation.
+    Cancel,
+    /// The user wants to close the window.
+    CloseRequested,
+    ConfirmClose,
+    KeepOpen,
+    SelectDevice(LedgerDevice),
+    /// Ask the user to confirm the removal of the app.
+    Uninstall(AppId),
+    ConfirmUninstall,
+    CancelUninstall,
+    /// Install again the app whose install failed.
+    RetryInstall(AppId),
+    DismissOutcome,
 
     ResetAlarm,
 }
@@ -36,17 +56,53 @@ pub enum Message {
 pub struct LedgerInstaller {
     ledger_sender: Sender<LedgerMessage>,
     ledger_receiver: Receiver<LedgerMessage>,
-    ledger_model: Option<String>,
-    ledger_version: Option<String>,
-    main_app_version: Version,
-    main_next_version: Version,
-    test_app_version: Version,
-    test_next_version: Version,
+    state: DeviceState,
+    devices: Vec<LedgerDevice>,
+    selected_device: Option<LedgerDevice>,
+    /// Newer versions of the apps, `Version::None` if up to date.
+    next_versions: HashMap<AppId, Version>,
+    /// The apps available for the device.
+    catalog: Vec<CatalogApp>,
+    /// The app of the catalog picked by the user.
+    selected_app: Option<AppId>,
+    /// Progress of the running install or removal.
+    progress: Option<HsmProgress>,
+    /// The app the user asked to remove, waiting for confirmation.
+    confirm_uninstall: Option<AppId>,
+    /// The running operation was asked to stop.
+    cancelling: bool,
+    /// An install or a removal was asked, the client hasn't started it yet.
+    request_pending: bool,
+    /// Closing the window was requested while that's not safe, waiting for confirmation.
+    confirm_close: bool,
+    /// How the last install or removal ended, until dismissed or another operation starts.
+    outcome: Option<(AppId, Outcome)>,
     user_message: Option<String>,
     alarm: bool,
 }
 
+/// How the last operation asked by the user ended.
+#[derive(Debug, Clone)]
+enum Outcome {
+    Install(InstallOutcome),
+    Uninstall(UninstallOutcome),
+}
+
 impl LedgerInstaller {
+    /// Whether an app is being written to the device, closing would leave it incomplete.
+    fn unsafe_to_close(&self) -> bool {
+        matches!(
+            self.state,
+            DeviceState::Installing(_) | DeviceState::Uninstalling(_)
+        ) && matches!(self.progress, Some(p) if p.phase == HsmPhase::Bulk)
+    }
+
+    fn close(&self) -> Command<Message> {
+        // Stop at the next safe point whatever is still running.
+        self.send_ledger_msg(LedgerMessage::Cancel);
+        window::close(window::Id::MAIN)
+    }
+
     #[allow(unused)]
     pub fn send_ledger_msg(&self, msg: LedgerMessage) {
         let sender = self.ledger_sender.clone();
@@ -64,12 +120,18 @@ impl Application for LedgerInstaller {
         let escrow = LedgerInstaller {
             ledger_sender: args.ledger_sender,
             ledger_receiver: args.ledger_receiver,
-            ledger_model: None,
-            ledger_version: None,
-            main_app_version: Version::None,
-            main_next_version: Version::None,
-            test_app_version: Version::None,
-            test_next_version: Version::None,
+            state: DeviceState::Disconnected,
+            devices: Vec::new(),
+            selected_device: None,
+            next_versions: HashMap::new(),
+            catalog: Vec::new(),
+            selected_app: None,
+            progress: None,
+            confirm_uninstall: None,
+            cancelling: false,
+            request_pending: false,
+            confirm_close: false,
+            outcome: None,
             user_message: None,
             alarm: false,
         };
@@ -85,24 +147,61 @@ impl Application for LedgerInstaller {
         log::debug!("Gui receive: {:?}", event.clone());
         match event {
             Message::LedgerClientMsg(ledger) => match ledger {
-                LedgerMessage::Connected(model, version) => {
-                    if model.is_none() {
-                        self.main_app_version = Version::None;
-                        self.main_next_version = Version::None;
-                        self.test_app_version = Version::None;
-                        self.test_next_version = Version::None;
+                LedgerMessage::State(state) => {
+                    // The next versions are sent again each time the apps are listed.
+                    if !matches!(state, DeviceState::Dashboard(_)) {
+                        self.next_versions.clear();
+                        self.confirm_uninstall = None;
+                    }
+                    if !matches!(
+                        state,
+                        DeviceState::Installing(_) | DeviceState::Uninstalling(_)
+                    ) {
+                        self.progress = None;
+                    }
+                    if matches!(
+                        state,
+                        DeviceState::Installing(_) | DeviceState::Uninstalling(_)
+                    ) {
+                        self.request_pending = false;
                     }
-                    self.ledger_model = model;
-                    self.ledger_version = version;
+                    self.cancelling = false;
+                    self.state = state;
+                }
+                LedgerMessage::Progress(progress) => self.progress = Some(progress),
+                LedgerMessage::InstallOutcome(app, outcome) => {
+                    self.request_pending = false;
+                    self.cancelling = false;
+                    self.outcome = Some((app, Outcome::Install(outcome)));
+                }
+                LedgerMessage::UninstallOutcome(app, outcome) => {
+                    self.request_pending = false;
+                    self.cancelling = false;
+                    self.outcome = Some((app, Outcome::Uninstall(outcome)));
+                }
+                LedgerMessage::NextVersion(app, version) => {
+                    self.next_versions.insert(app, version);
+                }
+                LedgerMessage::Catalog(apps) => self.catalog = apps,
+                LedgerMessage::Devices(devices, selected) => {
+                    self.devices = devices;
+                    self.selected_device = selected;
                 }
-                LedgerMessage::MainAppVersion(version) => self.main_app_version = version,
-                LedgerMessage::MainAppNextVersion(version) => self.main_next_version = version,
-                LedgerMessage::TestAppVersion(version) => self.test_app_version = version,
-                LedgerMessage::TestAppNextVersion(version) => self.test_next_version = version,
                 LedgerMessage::DisplayMessage(s, alarm) => {
                     self.user_message = Some(s);
                     self.alarm = alarm;
                 }
+                LedgerMessage::Error(e) => {
+                    // Expected conditions only need a hint, real failures must be acknowledged.
+                    self.alarm = !matches!(
+                        e,
+                        LedgerError::NoDevice
+                            | LedgerError::DeviceLocked(_)
+                            | LedgerError::UserRefused(_)
+                            | LedgerError::Cancelled
+                    );
+                    self.user_message = Some(e.to_string());
+                }
 
                 _ => {
                     log::debug!(
@@ -111,69 +210,253 @@ impl Application for LedgerInstaller {
                     )
                 }
             },
+            Message::SelectDevice(device) => {
+                self.selected_device = Some(device.clone());
+                self.send_ledger_msg(LedgerMessage::SelectDevice(device))
+            }
+            Message::Uninstall(app) => self.confirm_uninstall = Some(app),
+            Message::ConfirmUninstall => {
+                if let Some(app) = self.confirm_uninstall.take() {
+                    self.outcome = None;
+                    self.request_pending = true;
+                    self.send_ledger_msg(LedgerMessage::Uninstall(app))
+                }
+            }
+            Message::CancelUninstall => self.confirm_uninstall = None,
             Message::ResetAlarm => {
                 self.alarm = false;
                 self.user_message = None;
             }
-            Message::UpdateMain => { /*self.send_ledger_msg(LedgerMessage::UpdateMain)*/ }
-            Message::InstallMain => {
-                self.main_app_version = Version::None;
-                self.test_app_version = Version::None;
-                self.send_ledger_msg(LedgerMessage::InstallMain)
+            Message::Update(app) => {
+                self.outcome = None;
+                self.request_pending = true;
+                self.send_ledger_msg(LedgerMessage::Update(app))
+            }
+            Message::Install(app) => {
+                // Hide the buttons until the client confirms the install started.
+                self.outcome = None;
+                self.request_pending = true;
+                self.selected_app = None;
+                self.send_ledger_msg(LedgerMessage::Install(app))
             }
-            Message::UpdateTest => { /* self.send_ledger_msg(LedgerMessage::UpdateTest) */ }
-            Message::InstallTest => {
-                self.main_app_version = Version::None;
-                self.test_app_version = Version::None;
-                self.send_ledger_msg(LedgerMessage::InstallTest)
+            Message::RetryInstall(app) => {
+                // A failed update may have left the previous version in place.
+                let installed = match &self.state {
+                    DeviceState::Dashboard(session) => session
+                        .apps
+                        .iter()
+                        .any(|(id, version)| id == &app && version.is_installed()),
+                    _ => false,
+                };
+                return self.update(if installed {
+                    Message::Update(app)
+                } else {
+                    Message::Install(app)
+                });
             }
-            _ => {
-                log::debug!("LedgerInstaller.update() => Unhandled message {:?}", event)
+            Message::DismissOutcome => self.outcome = None,
+            Message::SelectApp(app) => self.selected_app = Some(app),
+            Message::Connect => self.send_ledger_msg(LedgerMessage::TryConnect),
+            Message::Cancel => {
+                self.cancelling = true;
+                self.send_ledger_msg(LedgerMessage::Cancel)
             }
+            Message::CloseRequested => {
+                if self.unsafe_to_close() {
+                    self.confirm_close = true;
+                } else {
+                    return self.close();
+                }
+            }
+            Message::ConfirmClose => return self.close(),
+            Message::KeepOpen => self.confirm_close = false,
         }
         Command::none()
     }
 
     fn view(&self) -> Element<'_, Message, Theme> {
-        let first_line = match (
-            &self.ledger_model,
-            &self.ledger_version,
-            self.alarm && self.user_message.is_some(),
-        ) {
-            (_, _, true) => Text::new(self.user_message.as_ref().unwrap()),
-            (Some(model), None, _) => Text::new(format!("Model: {}  Version: unknown ", model)),
-            (Some(model), Some(version), _) => {
-                Text::new(format!("Model: {}        Version: {}", model, version))
-            }
-            _ => Text::new("Please connect a device and unlock it..."),
+        let show_alarm = self.alarm && self.user_message.is_some();
+        let first_line = match (&self.state, show_alarm) {
+            (_, true) => Text::new(self.user_message.as_ref().unwrap()),
+            (DeviceState::Disconnected, _) => Text::new("Please connect a device and unlock it..."),
+            (DeviceState::Locked, _) => Text::new("Device locked, please unlock it with your PIN."),
+            (DeviceState::Dashboard(session), _) => Text::new(format!(
+                "Model: {}        Version: {}",
+                session.model, session.version
+            )),
+            (DeviceState::AppOpen(app), _) => Text::new(format!(
+                "The {} app is open, please close it to go back to the dashboard.",
+                app
+            )),
+            (DeviceState::AwaitingUserConfirmation(msg), _) => Text::new(msg),
+            (DeviceState::Installing(app), _) => Text::new(format!(
+                "Installing the {} app, please allow Ledger manager on device...",
+                app
+            )),
+            (DeviceState::Uninstalling(app), _) => Text::new(format!(
+                "Uninstalling the {} app, please allow Ledger manager on device...",
+                app
+            )),
+            (DeviceState::Bootloader(version), _) => Text::new(format!(
+                "Device in bootloader mode (version {}), please restart it.",
+                version
+            )),
+            (DeviceState::OsUpdater(version), _) => Text::new(format!(
+                "The device is updating its firmware to {}, please finish the update with Ledger Live.",
+                version
+            )),
+            (DeviceState::Error(e), _) => Text::new(e.to_string()),
         }
         .horizontal_alignment(Horizontal::Center);
 
-        let display_app =
-            self.ledger_model.is_some() && !(self.alarm && self.user_message.is_some());
+        let session = match &self.state {
+            DeviceState::Dashboard(session)
+                if !show_alarm && session.onboarded && !self.request_pending =>
+            {
+                Some(session)
+            }
+            _ => None,
+        };
+
+        // Removing an app can't be undone, the app rows are replaced by a confirmation.
+        let confirm_uninstall = session.and(self.confirm_uninstall.as_ref()).map(|app| {
+            Row::new()
+                .push(Space::with_width(Length::Fill))
+                .push(Text::new(format!(
+                    "Uninstall the {} app from the device?",
+                    app
+                )))
+                .push(Space::with_width(15))
+                .push(Button::new("Cancel").on_press(Message::CancelUninstall))
+                .push(Space::with_width(10))
+                .push(Button::new("Uninstall").on_press(Message::ConfirmUninstall))
+                .push(Space::with_width(Length::Fill))
+        });
+        let session = session.filter(|_| confirm_uninstall.is_none());
 
-        let main_app = if display_app {
-            Some(app_row(
-                "Bitcoin app",
-                &self.main_app_version,
-                Message::UpdateMain,
-                Message::InstallMain,
-            ))
-        } else {
-            None
+        let outcome = match (&self.state, &self.outcome) {
+            (DeviceState::Dashboard(_), Some((app, outcome))) if !show_alarm => {
+                let text = match outcome {
+                    Outcome::Install(InstallOutcome::Installed) => {
+                        format!("The {} app is installed.", app)
+                    }
+                    Outcome::Install(InstallOutcome::AlreadyInstalled) => {
+                        format!("The {} app was already installed.", app)
+                    }
+                    Outcome::Install(InstallOutcome::PartiallyInstalledCleaned(e)) => format!(
+                        "Fail to install the {} app: {} The incomplete app was removed.",
+                        app, e
+                    ),
+                    Outcome::Install(InstallOutcome::Failed(e)) => {
+                        format!("Fail to install the {} app: {}", app, e)
+                    }
+                    Outcome::Uninstall(UninstallOutcome::Uninstalled) => {
+                        format!("The {} app is uninstalled.", app)
+                    }
+                    Outcome::Uninstall(UninstallOutcome::Failed(e)) => {
+                        format!("Fail to uninstall the {} app: {}", app, e)
+                    }
+                };
+                let retry = match outcome {
+                    Outcome::Install(
+                        InstallOutcome::PartiallyInstalledCleaned(e) | InstallOutcome::Failed(e),
+                    ) if *e != LedgerError::Cancelled => {
+                        Some(Button::new("Retry").on_press(Message::RetryInstall(app.clone())))
+                    }
+                    _ => None,
+                };
+                Some(
+                    Row::new()
+                        .push(Space::with_width(Length::Fill))
+                        .push(Text::new(text))
+                        .push(Space::with_width(15))
+                        .push_maybe(retry)
+                        .push(Space::with_width(10))
+                        .push(Button::new("OK").on_press(Message::DismissOutcome))
+                        .push(Space::with_width(Length::Fill)),
+                )
+            }
+            _ => None,
         };
 
-        let test_app = if display_app {
-            Some(app_row(
-                "Testnet app",
-                &self.test_app_version,
-                Message::UpdateTest,
-                Message::InstallTest,
-            ))
-        } else {
-            None
+        let not_onboarded = match &self.state {
+            DeviceState::Dashboard(session) if !show_alarm && !session.onboarded => Some(
+                Row::new()
+                    .push(Space::with_width(Length::Fill))
+                    .push(Text::new(
+                        "This device is not set up yet, please set it up before installing apps.",
+                    ))
+                    .push(Space::with_width(Length::Fill)),
+            ),
+            _ => None,
         };
 
+        // Scrolls once the device holds more apps than the window can show.
+        let apps = session.map(|session| {
+            scrollable(
+                session
+                    .apps
+                    .iter()
+                    .fold(Column::new(), |column, (app, version)| {
+                        column
+                            .push(app_row(
+                                format!("{} app", app),
+                                version,
+                                self.next_versions.get(app).unwrap_or(&Version::None),
+                                Message::Update(app.clone()),
+                                Message::Install(app.clone()),
+                                Message::Uninstall(app.clone()),
+                            ))
+                            .push(Space::with_height(10))
+                    }),
+            )
+        });
+
+        // Any other app of the catalog can be installed too.
+        let other_apps = session.map(|session| {
+            let available: Vec<AppId> = self
+                .catalog
+                .iter()
+                .map(|app| app.id())
+                .filter(|id| !session.apps.iter().any(|(app, _)| app == id))
+                .collect();
+            let description = self
+                .selected_app
+                .as_ref()
+                .and_then(|id| self.catalog.iter().find(|app| app.is(id)))
+                .and_then(|app| app.description.clone());
+            let mut install = Button::new(
+                Text::new("Install")
+                    .size(11)
+                    .width(100)
+                    .horizontal_alignment(Horizontal::Center),
+            );
+            if let Some(app) = &self.selected_app {
+                install = install.on_press(Message::Install(app.clone()));
+            }
+            Column::new()
+                .push(
+                    Row::new()
+                        .push(Space::with_width(Length::Fill))
+                        .push(
+                            pick_list(available, self.selected_app.clone(), Message::SelectApp)
+                                .placeholder("Other apps")
+                                .text_size(11)
+                                .width(260)
+                                .style(theme::PickList::Secondary),
+                        )
+                        .push(Space::with_width(15))
+                        .push(install)
+                        .push(Space::with_width(Length::Fill)),
+                )
+                .push_maybe(description.map(|description| {
+                    Row::new()
+                        .push(Space::with_width(Length::Fill))
+                        .push(Text::new(description).size(11))
+                        .push(Space::with_width(Length::Fill))
+                }))
+        });
+
         let reset_alarm: Option<Row<Message, Theme, Renderer>> = if self.alarm {
             Some(
                 Row::new()
@@ -199,7 +482,136 @@ impl Application for LedgerInstaller {
             None
         };
 
+        // Only worth a choice when several devices are plugged in.
+        let device_picker = if self.devices.len() > 1 {
+            // The device being written to can't be switched away from.
+            let writing = self.request_pending
+                || matches!(
+                    self.state,
+                    DeviceState::Installing(_) | DeviceState::Uninstalling(_)
+                );
+            let picker: Element<'_, Message, Theme> = match &self.selected_device {
+                Some(device) if writing => Text::new(device.to_string()).size(11).into(),
+                _ => pick_list(
+                    self.devices.as_slice(),
+                    self.selected_device.clone(),
+                    Message::SelectDevice,
+                )
+                .placeholder("Select a device")
+                .text_size(11)
+                .style(theme::PickList::Secondary)
+                .into(),
+            };
+            Some(
+                Row::new()
+                    .push(Space::with_width(Length::Fill))
+                    .push(picker)
+                    .push(Space::with_width(Length::Fill)),
+            )
+        } else {
+            None
+        };
+
+        let progress = match &self.state {
+            DeviceState::Installing(_) | DeviceState::Uninstalling(_) if !show_alarm => {
+                self.progress.map(|progress| {
+                    let step = match progress.phase {
+                        HsmPhase::Connecting => "Connecting to Ledger's HSM...".to_string(),
+                        HsmPhase::Exchange => "Checking the device...".to_string(),
+                        HsmPhase::Bulk => format!(
+                            "Writing {}/{} ({} bytes sent)",
+                            progress.index, progress.total, progress.bytes_sent
+                        ),
+                        HsmPhase::Done => "Done.".to_string(),
+                    };
+                    Column::new()
+                        .push(
+                            Row::new()
+                                .push(Space::with_width(Length::Fill))
+                                .push(
+                                    ProgressBar::new(0.0..=1.0, progress.ratio())
+                                        .width(300)
+                                        .height(10)
+                                        .style(theme::ProgressBar::Simple),
+                                )
+                                .push(Space::with_width(Length::Fill)),
+                        )
+                        .push(Space::with_height(5))
+                        .push(
+                            Row::new()
+                                .push(Space::with_width(Length::Fill))
+                                .push(Text::new(step).size(11))
+                                .push(Space::with_width(Length::Fill)),
+                        )
+                })
+            }
+            _ => None,
+        };
+
+        let busy = self.request_pending
+            || matches!(
+                self.state,
+                DeviceState::Installing(_)
+                    | DeviceState::Uninstalling(_)
+                    | DeviceState::AwaitingUserConfirmation(_)
+            );
+        let cancel = match &self.state {
+            _ if busy && !show_alarm => {
+                // An install or a removal stops at its next exchange with the device. The apps
+                // listing is left at once, though the device keeps asking until answered.
+                let mut cancel = Button::new(if self.cancelling {
+                    "Cancelling..."
+                } else {
+                    "Cancel"
+                });
+                if !self.cancelling {
+                    cancel = cancel.on_press(Message::Cancel);
+                }
+                Some(
+                    Row::new()
+                        .push(Space::with_width(Length::Fill))
+                        .push(cancel)
+                        .push(Space::with_width(Length::Fill)),
+                )
+            }
+            DeviceState::Error(_) if !show_alarm => Some(
+                Row::new()
+                    .push(Space::with_width(Length::Fill))
+                    .push(Button::new("Retry").on_press(Message::Connect))
+                    .push(Space::with_width(Length::Fill)),
+            ),
+            _ => None,
+        };
+
+        let confirm_close = if self.confirm_close {
+            Some(
+                Column::new()
+                    .push(
+                        Row::new()
+                            .push(Space::with_width(Length::Fill))
+                            .push(Text::new(
+                                "The app is being written, closing now leaves it incomplete.",
+                            ))
+                            .push(Space::with_width(Length::Fill)),
+                    )
+                    .push(Space::with_height(5))
+                    .push(
+                        Row::new()
+                            .push(Space::with_width(Length::Fill))
+                            .push(Button::new("Keep running").on_press(Message::KeepOpen))
+                            .push(Space::with_width(10))
+                            .push(Button::new("Close anyway").on_press(Message::ConfirmClose))
+                            .push(Space::with_width(Length::Fill)),
+                    )
+                    .push(Space::with_height(10)),
+            )
+        } else {
+            None
+        };
+
         Column::new()
+            .push(Space::with_height(5))
+            .push_maybe(device_picker)
             .push(Space::with_height(Length::Fill))
             .push(
                 Row::new()
@@ -208,9 +620,15 @@ impl Application for LedgerInstaller {
                     .push(Space::with_width(Length::Fill)),
             )
             .push(Space::with_height(10))
-            .push_maybe(main_app)
-            .push(Space::with_height(10))
-            .push_maybe(test_app)
+            .push_maybe(confirm_close)
+            .push_maybe(not_onboarded)
+            .push_maybe(outcome)
+            .push_maybe(progress)
+            .push(Space::with_height(5))
+            .push_maybe(cancel)
+            .push_maybe(confirm_uninstall)
+            .push_maybe(apps)
+            .push_maybe(other_apps)
             .push_maybe(reset_alarm)
             .push(Space::with_height(Length::Fill))
             .push_maybe(user_message)
@@ -223,20 +641,28 @@ impl Application for LedgerInstaller {
     }
 
     fn subscription(&self) -> Subscription<Self::Message> {
-        Subscription::from_recipe(LedgerListener {
-            receiver: self.ledger_receiver.clone(),
-        })
+        Subscription::batch([
+            Subscription::from_recipe(LedgerListener {
+                receiver: self.ledger_receiver.clone(),
+            }),
+            event::listen_with(|event, _| match event {
+                Event::Window(_, window::Event::CloseRequested) => Some(Message::CloseRequested),
+                _ => None,
+            }),
+        ])
     }
 }
 
 fn app_row<'a>(
-    app_name: &'a str,
+    app_name: String,
     version: &Version,
+    next_version: &Version,
     update_msg: Message,
     install_msg: Message,
+    uninstall_msg: Message,
 ) -> Row<'a, Message, Theme, Renderer> {
     let button_text = match version {
-        Version::Installed(_) => "Try update".to_string(),
+        Version::Installed(_) | Version::UnknownBuild => "Try update".to_string(),
         Version::NotInstalled => "Install".to_string(),
         Version::None => "".to_string(),
     };
@@ -248,8 +674,11 @@ fn app_row<'a>(
     );
 
     match version {
-        Version::Installed(_) => {
-            // button = button.on_press(update_msg);
+        // Only enabled when a newer version is available.
+        Version::Installed(_) | Version::UnknownBuild => {
+            if let Version::Installed(_) = next_version {
+                button = button.on_press(update_msg);
+            }
         }
         Version::NotInstalled => {
             button = button.on_press(install_msg);
@@ -264,11 +693,27 @@ fn app_row<'a>(
                 Row::new()
                     .push(Text::new(app_name))
                     .push(Space::with_width(Length::Fill))
-                    .push(Text::new(version.to_string())),
+                    .push(Text::new(match next_version {
+                        Version::Installed(next) => format!("{} → {}", version, next),
+                        _ => version.to_string(),
+                    })),
             )
-            .width(220),
+            .width(260),
         )
         .push(Space::with_width(15))
         .push(button)
+        .push_maybe(
+            matches!(version, Version::Installed(_) | Version::UnknownBuild).then(|| {
+                Row::new().push(Space::with_width(10)).push(
+                    Button::new(
+                        Text::new("Uninstall")
+                            .size(11)
+                            .width(100)
+                            .horizontal_alignment(Horizontal::Center),
+                    )
+                    .on_press(uninstall_msg),
+                )
+            }),
+        )
         .push(Space::with_width(Length::Fill))
 }
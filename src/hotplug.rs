@@ -0,0 +1,124 @@
+use ledger_transport_hidapi::{hidapi::HidApi, TransportNativeHID};
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{error::LedgerError, model::Model};
+
+/// Delay between two enumerations of the Ledger HID devices.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A Ledger device seen on the HID bus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerDevice {
+    /// HID path, the only identity that is unique among several connected devices.
+    pub path: String,
+    pub product_id: u16,
+    pub model: Model,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl fmt::Display for LedgerDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.model, &self.product) {
+            (Model::Unknown, Some(product)) => write!(f, "{} ({})", product, self.path),
+            (model, _) => write!(f, "{} ({})", model, self.path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HotplugEvent {
+    /// A Ledger device showed up.
+    Arrived(LedgerDevice),
+    /// A Ledger device went away.
+    Removed(LedgerDevice),
+}
+
+/// Detect Ledger devices arrival and removal by diffing the HID enumeration. A single `HidApi` is
+/// kept for the whole session and its device list is refreshed, then filtered down to the Ledger
+/// devices. Enumerating doesn't open the devices, so no APDU is sent.
+pub struct DeviceWatcher {
+    api: Arc<Mutex<HidApi>>,
+    known: BTreeMap<String, LedgerDevice>,
+}
+
+impl DeviceWatcher {
+    pub fn new() -> Result<Self, LedgerError> {
+        let api = HidApi::new_without_enumerate()
+            .map_err(|e| LedgerError::Hid(format!("Error initializing HID api: {}.", e)))?;
+        Ok(Self {
+            api: Arc::new(Mutex::new(api)),
+            known: BTreeMap::new(),
+        })
+    }
+
+    /// The `HidApi` shared with the transports, always holding an up to date device list.
+    pub fn api(&self) -> Arc<Mutex<HidApi>> {
+        self.api.clone()
+    }
+
+    /// Enumerate the Ledger devices again and return what changed since the last call.
+    pub fn refresh(&mut self) -> Result<Vec<HotplugEvent>, LedgerError> {
+        let current: BTreeMap<String, LedgerDevice> = {
+            let mut api = self
+                .api
+                .lock()
+                .map_err(|_| LedgerError::Hid("HID api lock poisoned.".to_string()))?;
+            api.refresh_devices()
+                .map_err(|e| LedgerError::Hid(e.to_string()))?;
+            TransportNativeHID::list_ledgers(&api)
+                .map(|d| {
+                    let device = LedgerDevice {
+                        path: d.path().to_string_lossy().to_string(),
+                        product_id: d.product_id(),
+                        model: Model::from_usb_product_id(d.product_id()),
+                        product: d.product_string().map(|p| p.to_string()),
+                        serial_number: d.serial_number().map(|s| s.to_string()),
+                    };
+                    (device.path.clone(), device)
+                })
+                .collect()
+        };
+
+        let events = self
+            .known
+            .iter()
+            .filter(|(path, _)| !current.contains_key(*path))
+            .map(|(_, device)| HotplugEvent::Removed(device.clone()))
+            .chain(
+                current
+                    .iter()
+                    .filter(|(path, _)| !self.known.contains_key(*path))
+                    .map(|(_, device)| HotplugEvent::Arrived(device.clone())),
+            )
+            .collect();
+        self.known = current;
+        Ok(events)
+    }
+
+    /// Watch the devices from a dedicated thread until `on_event` returns false. Devices already
+    /// connected are reported as arrived.
+    pub fn spawn(mut self, mut on_event: impl FnMut(HotplugEvent) -> bool + Send + 'static) {
+        thread::spawn(move || loop {
+            match self.refresh() {
+                Ok(events) => {
+                    for event in events {
+                        log::debug!("Hotplug event: {:?}", event);
+                        if !on_event(event) {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Fail to enumerate Ledger devices: {}", e),
+            }
+            thread::sleep(WATCH_INTERVAL);
+        });
+    }
+}
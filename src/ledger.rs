@@ -1,32 +1,71 @@
 use crate::{
     client::ClientFn,
+    config::TransportConfig,
+    error::LedgerError,
     gui::Message,
     gui::Message::LedgerClientMsg,
+    hotplug::{DeviceWatcher, HotplugEvent, LedgerDevice},
     ledger_lib::{
-        bitcoin_app, list_installed_apps, query_via_websocket, DeviceInfo, BASE_SOCKET_URL,
-    }, ledger_manager::{device_info, ledger_api}, listener
+        get_app_and_version, list_installed_apps, query_via_websocket, CancelToken, DeviceInfo,
+        HsmProgress, InstalledApp, StatusCode, DASHBOARD_NAME,
+    },
+    ledger_manager::{device_info, ledger_api},
+    listener,
+    manager_api::{AppId, Application, CatalogApp, ManagerApi},
+    model::Model,
+    speculos::SpeculosTransport,
+    transcript::{Recorder, RecordingTransport},
+    transport::{Transport, TransportError},
 };
 
 use form_urlencoded::Serializer as UrlSerializer;
-use ledger_transport_hidapi::TransportNativeHID;
+use ledger_transport_hidapi::{hidapi::HidApi, LedgerHIDError, TransportNativeHID};
+use std::collections::VecDeque;
 use std::fmt::{Display, Formatter};
-use std::time::Duration;
+use std::future::Future;
+use std::panic;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::task::{self, JoinSet};
+use tokio::time::MissedTickBehavior;
 
 listener!(LedgerListener, LedgerMessage, Message, LedgerClientMsg);
 
-#[derive(Debug, Clone)]
+/// Delay between two checks of the device state, while waiting for the user to act on it. With
+/// HID, the device arrival and removal are reported by the [`DeviceWatcher`] instead.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Delay between two checks of a device sitting on its dashboard, when its arrival and removal
+/// are reported by the [`DeviceWatcher`]. Only its version is asked, to notice it was locked or an
+/// app was opened.
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Version {
     Installed(String),
+    /// Installed, but its hash matches none of the builds known by the manager.
+    UnknownBuild,
     NotInstalled,
     None,
 }
 
+impl Version {
+    /// Whether the app is on the device, even if its version is unknown.
+    pub fn is_installed(&self) -> bool {
+        !matches!(self, Version::NotInstalled)
+    }
+}
+
 impl Display for Version {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Version::Installed(version) => {
                 write!(f, "{}", version)
             }
+            Version::UnknownBuild => {
+                write!(f, "unknown build")
+            }
             Version::NotInstalled => {
                 write!(f, "Not installed!")
             }
@@ -37,396 +76,1079 @@ impl Display for Version {
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum Model {
-    NanoS,
-    NanoSP,
-    NanoX,
-    Unknown,
+/// What we know about a device sitting on its dashboard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceSession {
+    pub target_id: u32,
+    pub model: Model,
+    pub version: String,
+    /// Apps can only be installed once the device has been set up.
+    pub onboarded: bool,
+    /// The featured apps, installed or not, followed by the other installed apps.
+    pub apps: Vec<(AppId, Version)>,
 }
 
-impl Display for Model {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Model::NanoS => {
-                write!(f, "Nano S")
-            }
-            Model::NanoSP => {
-                write!(f, "Nano S+")
-            }
-            Model::NanoX => {
-                write!(f, "Nano X")
-            }
-            _ => {
-                write!(f, "")
-            }
-        }
-    }
+/// The state of the device as seen by the `LedgerClient`. Only the client loop changes it, the
+/// GUI renders from the last one it received.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceState {
+    Disconnected,
+    /// The device is connected but its PIN has not been entered.
+    Locked,
+    Dashboard(DeviceSession),
+    /// The named app is running on the device.
+    AppOpen(String),
+    /// Something must be approved on the device, the string says what.
+    AwaitingUserConfirmation(String),
+    /// The named app is being installed.
+    Installing(String),
+    /// The named app is being removed.
+    Uninstalling(String),
+    /// The device runs its bootloader, with the given version.
+    Bootloader(String),
+    /// The device runs the OS updater, a firmware update to the given version is under way.
+    OsUpdater(String),
+    Error(LedgerError),
 }
 
 #[derive(Debug, Clone)]
 pub enum LedgerMessage {
+    /// Install the app and its dependencies, sent by the GUI.
+    Install(AppId),
+    /// Replace the installed app by its latest version, sent by the GUI.
+    Update(AppId),
+    /// Remove the app from the device, sent by the GUI.
+    Uninstall(AppId),
+    /// Stop the running operation and drop the queued installs, sent by the GUI.
+    Cancel,
     #[allow(unused)]
-    UpdateMain,
-    InstallMain,
-    #[allow(unused)]
-    UpdateTest,
-    InstallTest,
     TryConnect,
 
-    Connected(Option<String>, Option<String>),
-    MainAppVersion(Version),
-    #[allow(unused)]
-    MainAppNextVersion(Version),
-    TestAppVersion(Version),
-    #[allow(unused)]
-    TestAppNextVersion(Version),
+    State(DeviceState),
+    Hotplug(HotplugEvent),
+    /// The connected devices and the selected one, sent to the GUI.
+    Devices(Vec<LedgerDevice>, Option<LedgerDevice>),
+    /// Bind the next operations to this device, sent by the GUI.
+    SelectDevice(LedgerDevice),
+    /// The newer version of the app available, `Version::None` if up to date.
+    NextVersion(AppId, Version),
+    /// The apps available for the device, sent to the GUI.
+    Catalog(Vec<CatalogApp>),
+    /// Progress of the install or removal running, sent to the GUI.
+    Progress(HsmProgress),
+    /// How the install of the app ended, sent to the GUI.
+    InstallOutcome(AppId, InstallOutcome),
+    /// How the removal of the app ended, sent to the GUI.
+    UninstallOutcome(AppId, UninstallOutcome),
     DisplayMessage(String, bool),
+    Error(LedgerError),
 }
 
 pub struct LedgerClient {
     sender: Sender<LedgerMessage>,
     receiver: Receiver<LedgerMessage>,
-    loopback: Sender<LedgerMessage>,
-    device_version: Option<String>,
-    mainnet_version: Version,
-    testnet_version: Version,
+    device: DeviceContext,
+    state: DeviceState,
+    /// Whether the device arrival and removal are reported by a `DeviceWatcher`.
+    hotplug: bool,
+    /// The Ledger devices connected through HID.
+    devices: Vec<LedgerDevice>,
+    /// The apps available for the device, as last listed.
+    catalog: Vec<CatalogApp>,
+    /// The operation currently running on the blocking thread pool, if any.
+    current: Option<Operation>,
+    /// When the device was last polled.
+    last_poll: Option<Instant>,
+    pending: VecDeque<Operation>,
+    running: JoinSet<OperationResult>,
+}
+
+/// How an install ended, as checked by listing the apps of the device afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstallOutcome {
+    Installed,
+    /// This build of the app was already on the device, nothing was done.
+    AlreadyInstalled,
+    /// The install failed midway, the incomplete app it left was removed.
+    PartiallyInstalledCleaned(LedgerError),
+    /// The install failed, leaving no incomplete app behind as far as we can tell.
+    Failed(LedgerError),
+}
+
+/// How the removal of an app ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UninstallOutcome {
+    Uninstalled,
+    Failed(LedgerError),
+}
+
+/// A blocking job run against the device.
+#[derive(Debug, Clone)]
+enum Operation {
+    Poll,
+    Install(AppId),
+    /// Replace the installed app by the latest version.
+    Update(AppId),
+    Uninstall(AppId),
+}
+
+/// What the client learns once an [`Operation`] completes.
+#[derive(Debug)]
+enum OperationResult {
+    /// The state of the device selected when the poll started.
+    Polled(Option<LedgerDevice>, DeviceState),
+    /// The install of the app ended.
+    Installed(AppId, InstallOutcome),
+    /// The removal of the app ended.
+    Uninstalled(AppId, UninstallOutcome),
 }
 
 impl LedgerClient {
+    /// Select the backend used to reach the device.
+    pub fn with_transport(mut self, transport: TransportConfig) -> Self {
+        self.device.transport = transport;
+        self
+    }
+
+    /// Record every exchange with the device.
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Self {
+        self.device.recorder = recorder;
+        self
+    }
+
+    /// Use other manager API endpoints than Ledger's ones.
+    pub fn with_manager_api(mut self, manager_api: ManagerApi) -> Self {
+        self.device.manager_api = manager_api;
+        self
+    }
+
     pub fn start(mut self) {
         tokio::spawn(async move {
             self.run().await;
         });
     }
+
     /// Send a LedgerMessage to the GUI via async-channel
     fn send_to_gui(&self, msg: LedgerMessage) {
-        let sender = self.sender.clone();
-        tokio::spawn(async move {
-            if sender.send(msg).await.is_err() {
-                log::debug!("LedgerClient.send_to_gui() -> Fail to send Message")
-            };
-        });
+        // The channel is unbounded, this never fails unless the GUI is gone.
+        if self.sender.try_send(msg).is_err() {
+            log::debug!("LedgerClient.send_to_gui() -> Fail to send Message")
+        };
     }
 
-    /// Handle a LedgerMessage received from the GUI via async-channel
+    /// Handle a LedgerMessage received from the GUI (or from a device task) via async-channel
     fn handle_message(&mut self, msg: LedgerMessage) {
-        match &msg {
-            LedgerMessage::TryConnect => {
-                if self.device_version.is_none() {
-                    self.poll_later();
-                    self.poll();
+        match msg {
+            LedgerMessage::TryConnect => self.schedule(Operation::Poll),
+            LedgerMessage::Install(app) => {
+                for dependency in self.missing_dependencies(&app) {
+                    self.schedule(Operation::Install(dependency));
+                }
+                self.schedule(Operation::Install(app))
+            }
+            LedgerMessage::Update(app) => self.schedule(Operation::Update(app)),
+            LedgerMessage::Uninstall(app) => {
+                let dependents = self.installed_dependents(&app);
+                if dependents.is_empty() {
+                    self.schedule(Operation::Uninstall(app));
+                } else {
+                    let error = LedgerError::RequiredBy(
+                        dependents.iter().map(ToString::to_string).collect(),
+                    );
+                    self.send_to_gui(LedgerMessage::UninstallOutcome(
+                        app,
+                        UninstallOutcome::Failed(error),
+                    ));
+                }
+            }
+            LedgerMessage::Cancel => {
+                // The apps queued after a cancelled dependency can't be installed anyway.
+                for op in std::mem::take(&mut self.pending) {
+                    match op {
+                        Operation::Poll => self.pending.push_back(op),
+                        Operation::Install(app) | Operation::Update(app) => {
+                            self.send_to_gui(LedgerMessage::InstallOutcome(
+                                app,
+                                InstallOutcome::Failed(LedgerError::Cancelled),
+                            ))
+                        }
+                        Operation::Uninstall(app) => {
+                            self.send_to_gui(LedgerMessage::UninstallOutcome(
+                                app,
+                                UninstallOutcome::Failed(LedgerError::Cancelled),
+                            ))
+                        }
+                    }
+                }
+                if self.current.is_some() {
+                    log::debug!("LedgerClient: cancelling {:?}", self.current);
+                    self.device.cancel.cancel();
+                }
+            }
+            LedgerMessage::NextVersion(..) => self.send_to_gui(msg),
+            LedgerMessage::Catalog(apps) => {
+                self.catalog = apps.clone();
+                self.send_to_gui(LedgerMessage::Catalog(apps));
+            }
+            LedgerMessage::State(state) => self.set_state(state),
+            LedgerMessage::Hotplug(HotplugEvent::Arrived(device)) => {
+                self.devices.push(device.clone());
+                if self.device.selected.is_none() {
+                    self.select(Some(device));
+                } else {
+                    self.send_devices();
+                }
+            }
+            LedgerMessage::Hotplug(HotplugEvent::Removed(device)) => {
+                self.devices.retain(|d| d.path != device.path);
+                if self.device.selected.as_ref() == Some(&device) {
+                    // Fall back on another connected device, if any.
+                    self.select(self.devices.first().cloned());
+                } else {
+                    self.send_devices();
+                }
+            }
+            LedgerMessage::SelectDevice(device) => {
+                if self.writing() {
+                    log::debug!("LedgerClient: an app is being written, keeping the device");
+                    // The GUI shows the device it asked for, put the selected one back.
+                    self.send_devices();
+                } else if self.device.selected.as_ref() != Some(&device)
+                    && self.devices.contains(&device)
+                {
+                    self.select(Some(device));
                 }
             }
-            LedgerMessage::UpdateMain => self.update_main(),
-            LedgerMessage::InstallMain => self.install_main(),
-            LedgerMessage::UpdateTest => self.update_test(),
-            LedgerMessage::InstallTest => self.install_test(),
             _ => {
                 log::debug!("LedgerClient.handle_message({:?}) -> unhandled!", msg)
             }
         }
     }
 
-    /// Delayed self sent message in order to call poll() later
-    fn poll_later(&self) {
-        let loopback = self.loopback.clone();
-        tokio::spawn(async move {
-            tokio::time::sleep(Duration::from_secs(2)).await;
-            if loopback.send(LedgerMessage::TryConnect).await.is_err() {
-                log::debug!("Fail to send Message")
-            };
-        });
+    fn set_state(&mut self, state: DeviceState) {
+        if state != self.state {
+            log::debug!("LedgerClient state: {:?} -> {:?}", self.state, state);
+            self.state = state.clone();
+            self.send_to_gui(LedgerMessage::State(state));
+        }
     }
 
-    /// Try to connect to the ledger device and get firmware and bitcoin apps versions
-    fn poll(&mut self) {
-        log::info!("Try to poll device...");
-        if let Some(transport) = self.connect() {
-            let mut device_version: Option<String> = None;
+    /// The apps `app` depends on that are not installed yet.
+    fn missing_dependencies(&self, app: &AppId) -> Vec<AppId> {
+        let installed = match &self.state {
+            DeviceState::Dashboard(session) => session.apps.as_slice(),
+            _ => &[],
+        };
+        self.catalog
+            .iter()
+            .find(|a| a.is(app))
+            .map(|a| a.dependencies())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|dependency| {
+                !installed
+                    .iter()
+                    .any(|(id, version)| id == dependency && version.is_installed())
+            })
+            .collect()
+    }
 
-            let info = match device_info(&transport) {
-                Ok(info) => {
-                    log::info!("Device connected");
-                    log::debug!("Device version: {}", &info.version);
-                    self.display_message(
-                        &format!("Device connected, version: {}", &info.version),
-                        false,
-                    );
-                    if self.device_version.is_none() {
-                        self.send_to_gui(LedgerMessage::Connected(
-                            Some("Ledger".to_string()),
-                            Some(info.version.clone()),
-                        ));
-                    }
-                    device_version = Some(info.version.clone());
-                    Some(info)
+    /// The installed apps depending on `app`, which can't be removed before them.
+    fn installed_dependents(&self, app: &AppId) -> Vec<AppId> {
+        let installed = match &self.state {
+            DeviceState::Dashboard(session) => session.apps.as_slice(),
+            _ => &[],
+        };
+        installed
+            .iter()
+            .filter(|(_, version)| version.is_installed())
+            .filter(|(id, _)| {
+                self.catalog
+                    .iter()
+                    .find(|a| a.is(id))
+                    .is_some_and(|a| a.dependencies().contains(app))
+            })
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Drop the queued installs of the apps depending on `app`, which failed to install.
+    fn drop_dependents(&mut self, app: &AppId) {
+        let depends_on = |id: &AppId| {
+            self.catalog
+                .iter()
+                .find(|a| a.is(id))
+                .is_some_and(|a| a.dependencies().contains(app))
+        };
+        let (dropped, kept) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|op| matches!(op, Operation::Install(id) if depends_on(id)));
+        self.pending = kept;
+        for op in dropped {
+            if let Operation::Install(dependent) = op {
+                log::debug!(
+                    "LedgerClient: {} can't be installed without {}",
+                    dependent,
+                    app
+                );
+                let error = LedgerError::MissingDependency(app.to_string());
+                self.send_to_gui(LedgerMessage::InstallOutcome(
+                    dependent,
+                    InstallOutcome::Failed(error),
+                ));
+            }
+        }
+    }
+
+    /// Whether an install or a removal is running or queued.
+    fn writing(&self) -> bool {
+        self.current
+            .iter()
+            .chain(self.pending.iter())
+            .any(|op| !matches!(op, Operation::Poll))
+    }
+
+    /// Bind the next operations to `device` and query it.
+    fn select(&mut self, device: Option<LedgerDevice>) {
+        log::debug!("LedgerClient.select({:?})", device);
+        let poll = device.is_some();
+        self.device.selected = device;
+        self.set_state(DeviceState::Disconnected);
+        self.send_devices();
+        if poll {
+            self.schedule(Operation::Poll);
+        }
+    }
+
+    fn send_devices(&self) {
+        self.send_to_gui(LedgerMessage::Devices(
+            self.devices.clone(),
+            self.device.selected.clone(),
+        ));
+    }
+
+    /// Start watching for HID devices, if that's the transport in use.
+    fn start_watcher(&mut self) {
+        if !matches!(self.device.transport, TransportConfig::Hid) {
+            return;
+        }
+        match DeviceWatcher::new() {
+            Ok(watcher) => {
+                self.device.hid = Some(watcher.api());
+                let loopback = self.device.loopback.clone();
+                watcher.spawn(move |event| {
+                    loopback
+                        .send_blocking(LedgerMessage::Hotplug(event))
+                        .is_ok()
+                });
+                self.hotplug = true;
+            }
+            Err(e) => log::warn!("Fail to start the device watcher, polling instead: {}", e),
+        }
+    }
+
+    /// Whether the state can change without the device being plugged or unplugged, so it must be
+    /// polled.
+    fn needs_polling(&self) -> bool {
+        // A transcript only holds one poll, it's replayed once.
+        if matches!(self.device.transport, TransportConfig::Replay(_)) {
+            return self.last_poll.is_none();
+        }
+        // Polling again would ask the user to confirm what they just cancelled or refused, the
+        // device is only polled again on retry or once replugged.
+        if matches!(
+            self.state,
+            DeviceState::Error(LedgerError::Cancelled | LedgerError::UserRefused(_))
+        ) {
+            return false;
+        }
+        if !self.hotplug {
+            return true;
+        }
+        match &self.state {
+            DeviceState::Locked
+            | DeviceState::AppOpen(_)
+            | DeviceState::Bootloader(_)
+            | DeviceState::OsUpdater(_)
+            | DeviceState::Error(_) => true,
+            DeviceState::Dashboard(session) if !session.onboarded => true,
+            // Hotplug doesn't tell when the device is locked or an app is opened.
+            DeviceState::Dashboard(_) => self
+                .last_poll
+                .is_none_or(|last| last.elapsed() >= PROBE_INTERVAL),
+            _ => false,
+        }
+    }
+
+    /// Queue an operation, it will run as soon as the device is free.
+    fn schedule(&mut self, op: Operation) {
+        let is_poll = |op: &Operation| matches!(op, Operation::Poll);
+        if is_poll(&op) && (self.current.iter().any(is_poll) || self.pending.iter().any(is_poll)) {
+            return;
+        }
+        self.pending.push_back(op);
+        self.start_next();
+    }
+
+    /// Run the next queued operation on the blocking thread pool, if the device is free.
+    fn start_next(&mut self) {
+        if self.current.is_some() {
+            return;
+        }
+        if let Some(op) = self.pending.pop_front() {
+            log::debug!("LedgerClient.start_next() -> {:?}", op);
+            self.current = Some(op.clone());
+            self.device.cancel = CancelToken::default();
+            let device = self.device.clone();
+            match op {
+                Operation::Poll => {
+                    self.last_poll = Some(Instant::now());
+                    // Apps are only listed again if the device changed or left the dashboard.
+                    let known = match &self.state {
+                        DeviceState::Dashboard(session) => Some(session.clone()),
+                        _ => None,
+                    };
+                    self.running.spawn_blocking(move || {
+                        OperationResult::Polled(device.selected.clone(), device.poll(known))
+                    });
                 }
-                Err(e) => {
-                    log::debug!("Failed connect device: {}", &e);
-                    self.display_message(&e, true);
-                    None
+                Operation::Install(app) | Operation::Update(app) => {
+                    let update = matches!(self.current, Some(Operation::Update(_)));
+                    self.set_state(DeviceState::Installing(app.to_string()));
+                    self.running.spawn_blocking(move || {
+                        let outcome = device.install(&app, update);
+                        OperationResult::Installed(app, outcome)
+                    });
+                }
+                Operation::Uninstall(app) => {
+                    self.set_state(DeviceState::Uninstalling(app.to_string()));
+                    self.running.spawn_blocking(move || {
+                        let outcome = device.uninstall(&app);
+                        OperationResult::Uninstalled(app, outcome)
+                    });
                 }
-            };
-
-            if let Some(info) = info {
-                // if it's our first connection, we check the if apps are installed & version
-                self.display_message("Querying installed apps. Please confirm on device.", false);
-                if self.device_version.is_none() && device_version.is_some() {
-                    if let Ok((main_installed, test_installed)) =
-                        self.check_apps_installed(&transport)
-                    {
-                        // get the mainnet app version name
-                        let (main_model, main_version) = if main_installed {
-                            match self.get_app_version(&info, true) {
-                                Ok((model, version)) => (model, version),
-                                Err(e) => {
-                                    self.display_message(&e, true);
-                                    (Model::Unknown, Version::None)
-                                }
-                            }
-                        } else {
-                            log::debug!("Mainnet app not installed!");
-                            // self.display_message("Mainnet app not installed!", false);
-                            (Model::Unknown, Version::NotInstalled)
-                        };
-
-                        // get the testnet app version name
-                        let (test_model, test_version) = if test_installed {
-                            match self.get_app_version(&info, true) {
-                                Ok((model, version)) => (model, version),
-                                Err(e) => {
-                                    self.display_message(&e, false);
-                                    (Model::Unknown, Version::None)
-                                }
-                            }
-                        } else {
-                            log::debug!("Testnet app not installed!");
-                            // self.display_message("Testnet app not installed!", false);
-                            (Model::Unknown, Version::NotInstalled)
-                        };
-
-                        let model = match (&main_model, &test_model) {
-                            (Model::Unknown, _) => test_model,
-                            _ => main_model,
-                        };
-                        self.send_to_gui(LedgerMessage::Connected(
-                            Some(model.to_string()),
-                            device_version.clone(),
-                        ));
-                        self.display_message("", false);
-                        self.mainnet_version = main_version;
-                        self.testnet_version = test_version;
-                        self.update_apps_version();
+            }
+        }
+    }
+
+    fn handle_result(&mut self, result: OperationResult) {
+        match result {
+            OperationResult::Polled(device, state) => {
+                if device == self.device.selected {
+                    self.set_state(state);
+                } else {
+                    log::debug!("Ignoring the state of the previously selected device");
+                    // The poll asked when selecting the new one was dropped, this one was running.
+                    if self.device.selected.is_some() {
+                        self.schedule(Operation::Poll);
                     }
                 }
-                self.device_version = device_version;
             }
-        } else {
-            self.send_to_gui(LedgerMessage::Connected(None, None));
-            log::debug!("No transport");
+            // Leaving the `Installing` state only once the device has been polled again, so the
+            // apps are listed anew.
+            OperationResult::Installed(app, outcome) => {
+                let installed = matches!(
+                    outcome,
+                    InstallOutcome::Installed | InstallOutcome::AlreadyInstalled
+                );
+                self.send_to_gui(LedgerMessage::InstallOutcome(app.clone(), outcome));
+                if !installed {
+                    self.drop_dependents(&app);
+                }
+                self.schedule(Operation::Poll);
+            }
+            OperationResult::Uninstalled(app, outcome) => {
+                self.send_to_gui(LedgerMessage::UninstallOutcome(app, outcome));
+                self.schedule(Operation::Poll);
+            }
         }
     }
+}
 
-    fn connect(&self) -> Option<TransportNativeHID> {
-        if let Some(api) = &ledger_api().ok() {
-            TransportNativeHID::new(api).ok()
-        } else {
-            None
+/// Everything needed to talk to the device and the manager API. It's cloned into the blocking
+/// tasks, so the client loop never waits on the device or the network.
+#[derive(Clone)]
+struct DeviceContext {
+    sender: Sender<LedgerMessage>,
+    /// Intermediate states go through the client loop, which owns the state.
+    loopback: Sender<LedgerMessage>,
+    transport: TransportConfig,
+    /// The `HidApi` kept up to date by the `DeviceWatcher`, if any.
+    hid: Option<Arc<Mutex<HidApi>>>,
+    /// The HID device to talk to, the first one found if unset.
+    selected: Option<LedgerDevice>,
+    recorder: Option<Arc<Recorder>>,
+    manager_api: ManagerApi,
+    /// Cancels the operation this context was cloned for.
+    cancel: CancelToken,
+}
+
+impl DeviceContext {
+    /// Send a LedgerMessage to the GUI via async-channel
+    fn send_to_gui(&self, msg: LedgerMessage) {
+        if self.sender.send_blocking(msg).is_err() {
+            log::debug!("DeviceContext.send_to_gui() -> Fail to send Message")
+        };
+    }
+
+    /// Send a LedgerMessage to the client loop, ahead of the result of the running operation.
+    fn send_to_client(&self, msg: LedgerMessage) {
+        if self.loopback.send_blocking(msg).is_err() {
+            log::debug!("DeviceContext.send_to_client() -> Fail to send Message")
+        };
+    }
+
+    /// Wait for a network future from the blocking thread running the operation, the runtime
+    /// keeps driving the sockets and the timers meanwhile.
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        Handle::current().block_on(future)
+    }
+
+    /// Wait for a manager API request, dropping it as soon as the operation is cancelled.
+    fn fetch<T, E>(&self, request: impl Future<Output = Result<T, E>>) -> Result<T, LedgerError>
+    where
+        LedgerError: From<E>,
+    {
+        Ok(self.block_on(self.cancel.run(request))??)
+    }
+
+    fn set_state(&self, state: DeviceState) {
+        self.send_to_client(LedgerMessage::State(state));
+    }
+
+    /// Try to connect to the ledger device and find out in which state it is. If it sits on the
+    /// dashboard, its installed bitcoin apps are listed unless it's the `known` one.
+    fn poll(&self, known: Option<DeviceSession>) -> DeviceState {
+        log::debug!("Try to poll device...");
+        match self.connect() {
+            Ok(transport) => self.poll_device(&transport, known),
+            Err(LedgerError::NoDevice) => DeviceState::Disconnected,
+            Err(e) => {
+                log::debug!("Fail to connect device: {}", &e);
+                DeviceState::Error(e)
+            }
         }
     }
 
-    fn check_apps_installed(&mut self, transport: &TransportNativeHID) -> Result<(bool, bool), ()> {
-        self.display_message("Querying installed apps. Please confirm on device.", false);
-        let mut mainnet = false;
-        let mut testnet = false;
-        match list_installed_apps(transport) {
-            Ok(apps) => {
-                log::debug!("List installed apps:");
-                self.display_message("List installed apps...", false);
-                for app in apps {
-                    log::debug!("  [{}]", &app.name);
-                    if app.name == "Bitcoin" {
-                        mainnet = true
-                    }
-                    if app.name == "Bitcoin Test" {
-                        testnet = true
-                    }
-                }
+    fn poll_device(
+        &self,
+        transport: &Arc<dyn Transport>,
+        known: Option<DeviceSession>,
+    ) -> DeviceState {
+        let info = match device_info(transport.as_ref()) {
+            Ok(info) => info,
+            Err(LedgerError::DeviceLocked(_)) => return DeviceState::Locked,
+            // The dashboard commands are rejected while an app is running.
+            Err(LedgerError::Device(status)) if app_is_running(status) => {
+                return match get_app_and_version(transport.as_ref()) {
+                    Ok(app) if app.name != DASHBOARD_NAME => DeviceState::AppOpen(app.name),
+                    Ok(_) => DeviceState::Error(LedgerError::Device(status)),
+                    Err(LedgerError::DeviceLocked(_)) => DeviceState::Locked,
+                    Err(e) => DeviceState::Error(e),
+                };
             }
             Err(e) => {
-                log::debug!("Error listing installed applications: {}.", e);
-                self.send_to_gui(LedgerMessage::DisplayMessage(
-                    format!("Error listing installed applications: {}.", e),
-                    true,
-                ));
-                return Err(());
+                log::debug!("Failed connect device: {}", &e);
+                return DeviceState::Error(e);
             }
+        };
+        if info.is_bootloader {
+            return DeviceState::Bootloader(info.version);
         }
-        if mainnet {
-            log::debug!("Mainnet App installed");
+        if info.is_osu {
+            return DeviceState::OsUpdater(info.version);
         }
-        if testnet {
-            log::debug!("Testnet App installed");
+        if let Some(session) = known.filter(|s| {
+            s.target_id == info.target_id
+                && s.version == info.version
+                && s.onboarded == info.status.onboarded
+        }) {
+            return DeviceState::Dashboard(session);
         }
-        self.display_message("", false);
-        Ok((mainnet, testnet))
-    }
-
-    fn get_app_version(
-        &mut self,
-        info: &DeviceInfo,
-        testnet: bool,
-    ) -> Result<(Model, Version), String> {
-        log::debug!("get_app_version()");
-        match bitcoin_app(info, testnet) {
-            Ok(r) => {
-                log::debug!("decoding app data");
-                // example for nano s
-                // BitcoinAppV2 { version_name: "Bitcoin Test", perso: "perso_11", delete_key: "nanos/2.1.0/bitcoin_testnet/app_2.2.1_del_key", firmware: "nanos/2.1.0/bitcoin_testnet/app_2.2.1", firmware_key: "nanos/2.1.0/bitcoin_testnet/app_2.2.1_key", hash: "7f07efc20d96faaf8c93bd179133c88d1350113169da914f88e52beb35fcdd1e" }
-                // example for nano s+
-                // BitcoinAppV2 { version_name: "Bitcoin Test", perso: "perso_11", delete_key: "nanos+/1.1.0/bitcoin_testnet/app_2.2.0-beta_del_key", firmware: "nanos+/1.1.0/bitcoin_testnet/app_2.2.0-beta", firmware_key: "nanos+/1.1.0/bitcoin_testnet/app_2.2.0-beta_key", hash: "3c6d6ebebb085da948c0211434b90bc4504a04a133b8d0621aa0ee91fd3a0b4f" }
-                if let Some(app) = r {
-                    let chunks: Vec<&str> = app.firmware.split('/').collect();
-                    let model = chunks.first().map(|m| m.to_string());
-                    let version = chunks.last().map(|m| m.to_string());
-                    if let (Some(model), Some(version)) = (model, version) {
-                        let model = if model == "nanos" {
-                            Model::NanoS
-                        } else if model == "nanos+" {
-                            Model::NanoSP
-                        } else if model == "nanox" {
-                            Model::NanoX
-                        } else {
-                            Model::Unknown
-                        };
-
-                        let version = if version.contains("app_") {
-                            version.replace("app_", "")
-                        } else {
-                            version
-                        };
-
-                        let version = Version::Installed(version);
-                        if testnet {
-                            log::debug!(
-                                "Testnet Model{}, Version{}",
-                                model.clone(),
-                                version.clone()
-                            );
-                        } else {
-                            log::debug!(
-                                "Mainnet Model{}, Version{}",
-                                model.clone(),
-                                version.clone()
-                            );
+        log::info!("Device connected");
+        log::debug!(
+            "Device version: {}, status: {:?}",
+            &info.version,
+            info.status
+        );
+        if !info.status.onboarded {
+            return DeviceState::Dashboard(DeviceSession {
+                target_id: info.target_id,
+                model: info.model(),
+                version: info.version,
+                onboarded: false,
+                apps: Vec::new(),
+            });
+        }
+
+        self.set_state(DeviceState::AwaitingUserConfirmation(
+            "Querying installed apps. Please confirm on device.".to_string(),
+        ));
+        let installed = match self.installed_apps(transport) {
+            Ok(installed) => installed,
+            Err(LedgerError::DeviceLocked(_)) => return DeviceState::Locked,
+            Err(e) => return DeviceState::Error(e),
+        };
+        if self.cancel.is_cancelled() {
+            return DeviceState::Error(LedgerError::Cancelled);
+        }
+
+        // The apps available for this device, also offered to the user.
+        let latest = match self.fetch(self.manager_api.apps_by_target(&info)) {
+            Ok(apps) => apps,
+            Err(LedgerError::Cancelled) => return DeviceState::Error(LedgerError::Cancelled),
+            Err(e) => {
+                log::debug!("Fail to get the latest apps: {}", e);
+                self.report_error(e);
+                Vec::new()
+            }
+        };
+        // All the versions of the apps are only needed to identify the builds installed on the
+        // device.
+        let catalog = if installed.is_empty() {
+            Some(Vec::new())
+        } else {
+            match self.fetch(self.manager_api.applications()) {
+                Ok(catalog) => Some(catalog),
+                Err(LedgerError::Cancelled) => return DeviceState::Error(LedgerError::Cancelled),
+                Err(e) => {
+                    log::debug!("Fail to get the apps catalog: {}", e);
+                    self.report_error(e);
+                    None
+                }
+            }
+        };
+
+        let mut apps: Vec<(AppId, Version)> = AppId::featured()
+            .into_iter()
+            .map(|id| (id, Version::NotInstalled))
+            .collect();
+        for app in &installed {
+            let version = installed_version(app, catalog.as_deref());
+            log::debug!("{} app version: {}", app.name, version);
+            match apps.iter_mut().find(|(id, _)| id.0 == app.name) {
+                Some(entry) => entry.1 = version,
+                None => apps.push((AppId(app.name.clone()), version)),
+            }
+        }
+
+        // Look for newer versions of the installed apps.
+        for (id, version) in &apps {
+            let next = latest
+                .iter()
+                .find(|app| app.is(id))
+                .and_then(|app| app.version())
+                .filter(|latest| is_update(version, latest))
+                .map(|latest| Version::Installed(latest.to_string()))
+                .unwrap_or(Version::None);
+            self.send_to_client(LedgerMessage::NextVersion(id.clone(), next));
+        }
+        self.send_to_client(LedgerMessage::Catalog(latest));
+
+        DeviceState::Dashboard(DeviceSession {
+            target_id: info.target_id,
+            model: info.model(),
+            version: info.version,
+            onboarded: true,
+            apps,
+        })
+    }
+
+    fn connect(&self) -> Result<Arc<dyn Transport>, LedgerError> {
+        let transport = self.open_transport()?;
+        Ok(match &self.recorder {
+            Some(recorder) => Arc::new(RecordingTransport::new(transport, recorder.clone())),
+            None => Arc::from(transport),
+        })
+    }
+
+    fn open_transport(&self) -> Result<Box<dyn Transport>, LedgerError> {
+        match &self.transport {
+            TransportConfig::Hid => {
+                let opened = match &self.hid {
+                    Some(api) => {
+                        let api = api
+                            .lock()
+                            .map_err(|_| LedgerError::Hid("HID api lock poisoned.".to_string()))?;
+                        match &self.selected {
+                            Some(device) => match api
+                                .device_list()
+                                .find(|d| d.path().to_string_lossy() == device.path)
+                            {
+                                Some(info) => TransportNativeHID::open_device(&api, info),
+                                None => Err(LedgerHIDError::DeviceNotFound),
+                            },
+                            None => TransportNativeHID::new(&api),
                         }
-                        Ok((model, version))
-                    } else {
-                        Err(format!("Failed to parse  model/version in {:?}", chunks))
                     }
-                } else {
-                    log::debug!("Fail to get version info");
-                    Err("Fail to get version info".to_string())
+                    None => TransportNativeHID::new(&ledger_api()?),
+                };
+                match opened {
+                    Ok(t) => Ok(Box::new(t)),
+                    Err(LedgerHIDError::DeviceNotFound) => Err(LedgerError::NoDevice),
+                    Err(e) => Err(TransportError::from(e).into()),
+                }
+            }
+            TransportConfig::Speculos {
+                apdu_addr,
+                api_url,
+                auto_approve,
+            } => match SpeculosTransport::connect(apdu_addr, api_url.clone(), *auto_approve) {
+                Ok(t) => Ok(Box::new(t)),
+                Err(e) => {
+                    log::debug!("Fail to connect to Speculos at {}: {}", apdu_addr, e);
+                    Err(LedgerError::NoDevice)
+                }
+            },
+            TransportConfig::Replay(replay) => Ok(Box::new(replay.clone())),
+            TransportConfig::Mock(device) if !device.is_plugged() => Err(LedgerError::NoDevice),
+            TransportConfig::Mock(device) => Ok(Box::new(device.clone())),
+        }
+    }
+
+    /// List the installed apps, which the user must confirm on the device. A cancel doesn't wait
+    /// for the answer to leave the confirmation, but the device is only released once answered.
+    fn installed_apps(
+        &self,
+        transport: &Arc<dyn Transport>,
+    ) -> Result<Vec<InstalledApp>, LedgerError> {
+        self.display_message("Querying installed apps. Please confirm on device.", false);
+        let listing = transport.clone();
+        let mut handle = task::spawn_blocking(move || list_installed_apps(listing.as_ref()));
+        let listed = match self.block_on(self.cancel.run(&mut handle)) {
+            Ok(listed) => listed,
+            Err(e) => {
+                self.set_state(DeviceState::Error(e.clone()));
+                self.display_message("", false);
+                let _ = self.block_on(handle);
+                return Err(e);
+            }
+        };
+        match listed.unwrap_or_else(|e| panic::resume_unwind(e.into_panic())) {
+            Ok(apps) => {
+                log::debug!("List installed apps:");
+                for app in &apps {
+                    log::debug!("  [{}] {}", &app.name, hex::encode(&app.hash));
                 }
+                self.display_message("", false);
+                Ok(apps)
             }
             Err(e) => {
-                log::debug!("Fail to get version info: {}", e);
-                Err(format!("Fail to get version info: {}", e))
+                log::debug!("Error listing installed applications: {}.", e);
+                self.report_error(e.clone());
+                Err(e)
             }
         }
     }
 
-    fn update_apps_version(&self) {
-        match &self.mainnet_version {
-            Version::None => {}
-            _ => {
-                self.send_to_gui(LedgerMessage::MainAppVersion(self.mainnet_version.clone()));
+    fn install(&self, app: &AppId, update: bool) -> InstallOutcome {
+        let outcome = self
+            .install_app(app, update)
+            .unwrap_or_else(InstallOutcome::Failed);
+        log::debug!("Install of the {} app: {:?}", app, outcome);
+        self.display_message("", false);
+        outcome
+    }
+
+    /// Install the latest version of the app, unless this build is already installed. When
+    /// updating, the installed version is removed first. The install is then checked by listing
+    /// the apps again.
+    fn install_app(&self, app: &AppId, update: bool) -> Result<InstallOutcome, LedgerError> {
+        log::debug!("install_app({}, update={})", app, update);
+        let api = self.connect()?;
+        self.display_message("Get device info from API...", false);
+        let device_info = self.dashboard_info(&api)?;
+        // Only the apps built for the firmware of the device are listed.
+        let catalog = self.fetch(self.manager_api.apps_by_target(&device_info))?;
+        if catalog.is_empty() {
+            return Err(LedgerError::UnsupportedFirmware(device_info.version));
+        }
+        let catalog_app = catalog
+            .into_iter()
+            .find(|a| a.is(app))
+            .ok_or_else(|| LedgerError::AppNotAvailable(app.to_string()))?;
+        self.cancel.check()?;
+        self.display_message("Querying installed apps. Please confirm on device.", false);
+        let before = list_installed_apps(&api)?;
+        if before.iter().any(|a| catalog_app.has_hash(&a.hash)) {
+            return Ok(InstallOutcome::AlreadyInstalled);
+        }
+        // Only an update replaces the installed version.
+        if !update
+            && before
+                .iter()
+                .any(|a| catalog_app.is(&AppId(a.name.clone())))
+        {
+            return Err(LedgerError::AppAlreadyInstalled(app.to_string()));
+        }
+        check_storage(&device_info, &catalog_app, &before, update)?;
+        self.cancel.check()?;
+        self.display_message(
+            "Installing, please allow Ledger manager on device...",
+            false,
+        );
+        if update {
+            self.display_message("Remove the installed app...", false);
+            self.remove_app(&api, &device_info, &catalog_app)?;
+            self.cancel.check()?;
+        }
+        let install_ws_url = self.install_url(
+            &device_info,
+            &catalog_app,
+            &catalog_app.firmware,
+            &catalog_app.firmware_key,
+        );
+        self.display_message("Install app...", false);
+        let session = self.run_hsm_session(&api, &install_ws_url, &self.cancel);
+        self.display_message("Checking the installed apps...", false);
+        Ok(self.verify_install(&api, &device_info, &catalog_app, &before, session))
+    }
+
+    /// Tell how the install went from the apps listed by the device, whatever the HSM session
+    /// returned. An app left incomplete is removed.
+    fn verify_install(
+        &self,
+        transport: &Arc<dyn Transport>,
+        device_info: &DeviceInfo,
+        app: &CatalogApp,
+        before: &[InstalledApp],
+        session: Result<(), LedgerError>,
+    ) -> InstallOutcome {
+        let after = match list_installed_apps(transport) {
+            Ok(after) => after,
+            Err(e) => {
+                log::warn!("Fail to list the apps after the install: {}", e);
+                return InstallOutcome::Failed(session.err().unwrap_or(e));
+            }
+        };
+        if after.iter().any(|a| app.has_hash(&a.hash)) {
+            if let Err(e) = session {
+                log::warn!("The install reported an error but the app is there: {}", e);
             }
+            return InstallOutcome::Installed;
         }
-        match &self.testnet_version {
-            Version::None => {}
-            _ => {
-                self.send_to_gui(LedgerMessage::TestAppVersion(self.testnet_version.clone()));
+
+        let error = session.err().unwrap_or_else(|| {
+            LedgerError::Hsm("The app is not on the device after the install.".to_string())
+        });
+        // An app of this name that was not there before can only be what's left of the install.
+        let partial = after
+            .iter()
+            .any(|a| app.is(&AppId(a.name.clone())) && !before.iter().any(|b| b.hash == a.hash));
+        if !partial {
+            return InstallOutcome::Failed(error);
+        }
+        log::warn!("The {} app is incomplete, removing it.", app.version_name);
+        self.display_message("Removing the incomplete app...", false);
+        let delete_ws_url = self.install_url(device_info, app, &app.delete, &app.delete_key);
+        // The install may have been cancelled, the cleanup must run anyway.
+        match self.run_hsm_session(transport, &delete_ws_url, &CancelToken::default()) {
+            Ok(()) => InstallOutcome::PartiallyInstalledCleaned(error),
+            Err(e) => {
+                log::warn!("Fail to remove the incomplete app: {}", e);
+                InstallOutcome::Failed(error)
             }
         }
     }
 
-    fn install(&mut self, testnet: bool) {
-        self.send_to_gui(LedgerMessage::MainAppVersion(Version::None));
-        self.send_to_gui(LedgerMessage::TestAppVersion(Version::None));
-
-        self.install_app(testnet);
+    fn uninstall(&self, app: &AppId) -> UninstallOutcome {
+        let outcome = match self.uninstall_app(app) {
+            Ok(()) => UninstallOutcome::Uninstalled,
+            Err(e) => UninstallOutcome::Failed(e),
+        };
+        log::debug!("Removal of the {} app: {:?}", app, outcome);
+        self.display_message("", false);
+        outcome
+    }
 
-        self.device_version = None;
-        self.poll();
+    fn uninstall_app(&self, app: &AppId) -> Result<(), LedgerError> {
+        log::debug!("uninstall_app({})", app);
+        let api = self.connect()?;
+        self.display_message("Get device info from API...", false);
+        let device_info = self.dashboard_info(&api)?;
+        let catalog_app = self
+            .fetch(self.manager_api.app(&device_info, app))?
+            .ok_or_else(|| LedgerError::AppNotAvailable(app.to_string()))?;
+        self.display_message(
+            "Uninstalling, please allow Ledger manager on device...",
+            false,
+        );
+        self.remove_app(&api, &device_info, &catalog_app)
     }
 
-    fn install_app(&mut self, testnet: bool) {
-        log::debug!("install_app(testnet={})", testnet);
-        if let Some(api) = self.connect() {
-            self.display_message("Get device info from API...", false);
-            if let Ok(device_info) = device_info(&api) {
-                let bitcoin_app = match bitcoin_app(&device_info, testnet) {
-                    Ok(Some(a)) => a,
-                    Ok(None) => {
-                        self.display_message("Could not get info about Bitcoin app.", true);
-                        return;
-                    }
-                    Err(e) => {
-                        self.display_message(
-                            &format!("Error querying info about Bitcoin app: {}.", e),
-                            true,
-                        );
-                        return;
-                    }
-                };
-                self.display_message(
-                    "Installing, please allow Ledger manager on device...",
-                    false,
-                );
-                // Now install the app by connecting through their websocket thing to their HSM. Make sure to
-                // properly escape the parameters in the request's parameter.
-                let install_ws_url = UrlSerializer::new(format!("{}/install?", BASE_SOCKET_URL))
-                    .append_pair("targetId", &device_info.target_id.to_string())
-                    .append_pair("perso", &bitcoin_app.perso)
-                    .append_pair("deleteKey", &bitcoin_app.delete_key)
-                    .append_pair("firmware", &bitcoin_app.firmware)
-                    .append_pair("firmwareKey", &bitcoin_app.firmware_key)
-                    .append_pair("hash", &bitcoin_app.hash)
-                    .finish();
-                self.display_message("Install app...", false);
-                if let Err(e) = query_via_websocket(&api, &install_ws_url) {
-                    self.display_message(&format!("Got an error when installing Bitcoin app from Ledger's remote HSM: {}.", e), false);
-                    return;
-                }
-                self.display_message("Successfully installed the app.", false);
-            } else {
-                self.display_message("Fail to fetch device info!", true);
+    /// Get the device info, checking the device is unlocked, set up and on its dashboard, so
+    /// the HSM is only reached when it can do its job.
+    fn dashboard_info(&self, transport: &dyn Transport) -> Result<DeviceInfo, LedgerError> {
+        let info = match device_info(transport) {
+            Ok(info) => info,
+            // The dashboard commands are rejected while an app is running.
+            Err(LedgerError::Device(status)) if app_is_running(status) => {
+                return Err(match get_app_and_version(transport) {
+                    Ok(app) if app.name != DASHBOARD_NAME => LedgerError::AppOpen(app.name),
+                    Ok(_) => LedgerError::Device(status),
+                    Err(e) => e,
+                });
             }
-        } else {
-            self.display_message("Fail to connect to device!", true);
+            Err(e) => return Err(e),
+        };
+        if info.is_bootloader || info.is_osu {
+            return Err(LedgerError::UnsupportedFirmware(info.version));
+        }
+        if !info.status.onboarded {
+            return Err(LedgerError::NotOnboarded(None));
         }
+        Ok(info)
     }
 
-    fn install_main(&mut self) {
-        self.install(false);
+    /// Remove `app` from the device. The HSM removes an app when given its delete script in place
+    /// of the app itself.
+    fn remove_app(
+        &self,
+        transport: &Arc<dyn Transport>,
+        device_info: &DeviceInfo,
+        app: &CatalogApp,
+    ) -> Result<(), LedgerError> {
+        let delete_ws_url = self.install_url(device_info, app, &app.delete, &app.delete_key);
+        self.run_hsm_session(transport, &delete_ws_url, &self.cancel)
     }
 
-    fn update_main(&mut self) {
-        self.install(false);
+    /// Let the HSM drive the device until `cancel` is triggered, reporting the progress to the
+    /// GUI.
+    fn run_hsm_session(
+        &self,
+        transport: &Arc<dyn Transport>,
+        url: &str,
+        cancel: &CancelToken,
+    ) -> Result<(), LedgerError> {
+        self.block_on(query_via_websocket(
+            transport,
+            url,
+            self.manager_api.hsm_timeout,
+            cancel,
+            &mut |progress| self.send_to_gui(LedgerMessage::Progress(progress)),
+        ))
     }
 
-    fn install_test(&mut self) {
-        self.install(true);
+    /// Url of the HSM endpoint running `firmware` on the device.
+    fn install_url(
+        &self,
+        device_info: &DeviceInfo,
+        app: &CatalogApp,
+        firmware: &str,
+        firmware_key: &str,
+    ) -> String {
+        // Connect through their websocket thing to their HSM. Make sure to properly escape the
+        // parameters in the request's parameter.
+        UrlSerializer::new(format!("{}/install?", self.manager_api.socket_url))
+            .append_pair("targetId", &device_info.target_id.to_string())
+            .append_pair("perso", &app.perso)
+            .append_pair("deleteKey", &app.delete_key)
+            .append_pair("firmware", firmware)
+            .append_pair("firmwareKey", firmware_key)
+            .append_pair("hash", &app.hash)
+            .finish()
     }
 
-    fn update_test(&mut self) {
-        self.install(true);
+    fn display_message(&self, msg: &str, alarm: bool) {
+        self.send_to_gui(LedgerMessage::DisplayMessage(msg.to_string(), alarm));
     }
 
-    fn display_message(&mut self, msg: &str, alarm: bool) {
-        self.send_to_gui(LedgerMessage::DisplayMessage(msg.to_string(), alarm));
+    fn report_error(&self, error: LedgerError) {
+        log::debug!("LedgerClient error: {:?}", error);
+        self.send_to_gui(LedgerMessage::Error(error));
+    }
+}
+
+/// Fail if `app` can't fit beside the `installed` apps, the installed version being removed first
+/// on update. The size of the firmware is unknown, so only the apps that can't fit at all are
+/// caught here, the device has the last word.
+fn check_storage(
+    device_info: &DeviceInfo,
+    app: &CatalogApp,
+    installed: &[InstalledApp],
+    update: bool,
+) -> Result<(), LedgerError> {
+    let model = device_info.model();
+    let (Some(block_size), Some(memory_size)) =
+        (model.block_size(&device_info.version), model.memory_size())
+    else {
+        return Ok(());
+    };
+    let Some(needed) = app.blocks(block_size) else {
+        return Ok(());
+    };
+    let used: u32 = installed
+        .iter()
+        .filter(|a| !(update && app.is(&AppId(a.name.clone()))))
+        .map(|a| a.blocks as u32)
+        .sum();
+    let free = (memory_size / block_size).saturating_sub(used);
+    if needed > free {
+        log::debug!(
+            "{} needs {} blocks, {} are free",
+            app.version_name,
+            needed,
+            free
+        );
+        return Err(LedgerError::InsufficientStorage(
+            needed * block_size,
+            free * block_size,
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a status word answered to a dashboard command means an app is running.
+fn app_is_running(status: u16) -> bool {
+    matches!(
+        StatusCode::from_u16(status),
+        Some(StatusCode::CLA_NOT_SUPPORTED)
+            | Some(StatusCode::INS_NOT_SUPPORTED)
+            | Some(StatusCode::UNKNOWN_APDU)
+    )
+}
+
+/// Whether `latest` is newer than the `installed` version. Builds unknown to the manager are
+/// always worth replacing.
+fn is_update(installed: &Version, latest: &str) -> bool {
+    // Numeric parts of the version, ignoring any pre-release suffix.
+    let parse = |v: &str| -> Option<Vec<u32>> {
+        v.split('-')
+            .next()?
+            .split('.')
+            .map(|n| n.parse().ok())
+            .collect()
+    };
+    match installed {
+        Version::Installed(current) => match (parse(current), parse(latest)) {
+            (Some(current), Some(latest)) => latest > current,
+            _ => current != latest,
+        },
+        Version::UnknownBuild => true,
+        Version::NotInstalled | Version::None => false,
+    }
+}
+
+/// Resolve the version of an installed app by looking its hash up in the manager catalog.
+fn installed_version(app: &InstalledApp, catalog: Option<&[Application]>) -> Version {
+    let catalog = match catalog {
+        Some(catalog) => catalog,
+        None => return Version::None,
+    };
+    match catalog
+        .iter()
+        .filter(|a| a.name.eq_ignore_ascii_case(&app.name))
+        .find_map(|a| a.version_by_hash(&app.hash))
+    {
+        Some(version) => Version::Installed(version.version.clone()),
+        None => {
+            log::debug!(
+                "No known build of {} has the hash {}",
+                app.name,
+                hex::encode(&app.hash)
+            );
+            Version::UnknownBuild
+        }
     }
 }
 
@@ -437,24 +1159,587 @@ impl ClientFn<LedgerMessage, Sender<LedgerMessage>> for LedgerClient {
         loopback: Sender<LedgerMessage>,
     ) -> Self {
         LedgerClient {
-            sender,
+            sender: sender.clone(),
             receiver,
-            loopback,
-            device_version: None,
-            mainnet_version: Version::None,
-            testnet_version: Version::None,
+            device: DeviceContext {
+                sender,
+                loopback,
+                transport: TransportConfig::Hid,
+                hid: None,
+                selected: None,
+                recorder: None,
+                manager_api: ManagerApi::default(),
+                cancel: CancelToken::default(),
+            },
+            state: DeviceState::Disconnected,
+            hotplug: false,
+            devices: Vec::new(),
+            catalog: Vec::new(),
+            current: None,
+            last_poll: None,
+            pending: VecDeque::new(),
+            running: JoinSet::new(),
         }
     }
 
     async fn run(&mut self) {
-        self.poll();
-        self.poll_later();
+        self.start_watcher();
+        // The first tick completes immediately, so the device is polled at startup.
+        let mut poll_timer = tokio::time::interval(POLL_INTERVAL);
+        poll_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
-            if let Ok(msg) = self.receiver.try_recv() {
-                self.handle_message(msg);
+            tokio::select! {
+                // Messages first: the intermediate states sent by a device task must be handled
+                // before its result.
+                biased;
+                msg = self.receiver.recv() => match msg {
+                    Ok(msg) => self.handle_message(msg),
+                    Err(_) => {
+                        log::debug!("LedgerClient.run() -> channel closed, stopping");
+                        break;
+                    }
+                },
+                _ = poll_timer.tick() => {
+                    if self.needs_polling() {
+                        self.schedule(Operation::Poll);
+                    }
+                }
+                Some(result) = self.running.join_next() => {
+                    self.current = None;
+                    match result {
+                        Ok(result) => self.handle_result(result),
+                        Err(e) => log::error!("LedgerClient device task failed: {}", e),
+                    }
+                    self.start_next();
+                }
             }
-            // cpu load is not visible w/ 1ns but we can increase it w/o performance penalty
-            tokio::time::sleep(Duration::from_nanos(1)).await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ledger_lib::open_app,
+        manager_api::StubBackend,
+        mock_device::{MockApp, MockDevice},
+        mock_hsm::{HsmScript, MockHsm},
+        transcript::{ReplayTransport, Transcript},
+    };
+    use tokio::time::timeout;
+
+    const BITCOIN_HASH: [u8; 32] = [0xb4; 32];
+    /// Size of the app on a Nano X, in blocks of 4 KiB.
+    const BITCOIN_BLOCKS: u16 = 20;
+
+    /// The manager API knowing a single build of Bitcoin, for a Nano X on 2.2.3.
+    fn manager_api(socket_url: String) -> ManagerApi {
+        let hash = hex::encode(BITCOIN_HASH);
+        let firmware = "nanox/2.2.3/bitcoin/app_2.2.2";
+        let app = serde_json::json!([{
+            "versionName": "Bitcoin",
+            "bytes": BITCOIN_BLOCKS as u32 * 4096,
+            "perso": "perso_11",
+            "delete": format!("{}_del", firmware),
+            "deleteKey": format!("{}_del_key", firmware),
+            "firmware": firmware,
+            "firmwareKey": format!("{}_key", firmware),
+            "hash": hash,
+        }]);
+        let catalog = serde_json::json!([{
+            "name": "Bitcoin",
+            "application_versions": [
+                {"name": "Bitcoin", "version": "2.2.2", "firmware": firmware, "hash": hash},
+            ],
+        }]);
+        let stub = StubBackend::default();
+        stub.respond("/apps/by-target", 200, &app.to_string());
+        stub.respond("/applications", 200, &catalog.to_string());
+        let mut api = ManagerApi::default().with_http_backend(Arc::new(stub));
+        api.socket_url = socket_url;
+        api
+    }
+
+    fn bitcoin() -> MockApp {
+        MockApp::new("Bitcoin", BITCOIN_HASH, BITCOIN_BLOCKS)
+    }
+
+    /// Start a client driving `device`, returning what it sends to the GUI and its own inbox.
+    fn start_client(
+        device: Arc<MockDevice>,
+        manager_api: ManagerApi,
+    ) -> (Receiver<LedgerMessage>, Sender<LedgerMessage>) {
+        let (gui_sender, gui_receiver) = async_channel::unbounded();
+        let (client_sender, client_receiver) = async_channel::unbounded();
+        LedgerClient::new(gui_sender, client_receiver, client_sender.clone())
+            .with_transport(TransportConfig::Mock(device))
+            .with_manager_api(manager_api)
+            .start();
+        (gui_receiver, client_sender)
+    }
+
+    /// The first message sent to the GUI `f` picks something from.
+    async fn expect<T>(
+        gui: &Receiver<LedgerMessage>,
+        mut f: impl FnMut(LedgerMessage) -> Option<T>,
+    ) -> T {
+        loop {
+            let msg = timeout(Duration::from_secs(10), gui.recv())
+                .await
+                .expect("The expected message never came")
+                .unwrap();
+            if let Some(t) = f(msg) {
+                return t;
+            }
+        }
+    }
+
+    /// The next state sent to the GUI, other than waiting for the user.
+    async fn next_state(gui: &Receiver<LedgerMessage>) -> DeviceState {
+        expect(gui, |msg| match msg {
+            LedgerMessage::State(DeviceState::AwaitingUserConfirmation(_)) => None,
+            LedgerMessage::State(state) => Some(state),
+            _ => None,
+        })
+        .await
+    }
+
+    async fn next_session(gui: &Receiver<LedgerMessage>) -> DeviceSession {
+        match next_state(gui).await {
+            DeviceState::Dashboard(session) => session,
+            state => panic!("Expected the dashboard, got {:?}", state),
+        }
+    }
+
+    fn version(session: &DeviceSession, app: &AppId) -> Version {
+        session
+            .apps
+            .iter()
+            .find(|(id, _)| id == app)
+            .map(|(_, version)| version.clone())
+            .unwrap()
+    }
+
+    /// A context driving `transport` on its own, its messages are dropped.
+    fn context(transport: TransportConfig, manager_api: ManagerApi) -> DeviceContext {
+        let (sender, _) = async_channel::unbounded();
+        let (loopback, receiver) = async_channel::unbounded();
+        let client = LedgerClient::new(sender, receiver, loopback)
+            .with_transport(transport)
+            .with_manager_api(manager_api);
+        client.device
+    }
+
+    async fn install_with(
+        device: Arc<MockDevice>,
+        manager_api: ManagerApi,
+    ) -> Result<InstallOutcome, LedgerError> {
+        let context = context(TransportConfig::Mock(device), manager_api);
+        tokio::task::spawn_blocking(move || context.install_app(&AppId::bitcoin(), false))
+            .await
+            .unwrap()
+    }
+
+    async fn install(device: Arc<MockDevice>) -> Result<InstallOutcome, LedgerError> {
+        install_with(device, manager_api(String::new())).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn state_transitions() {
+        let device = Arc::new(MockDevice::new(Model::NanoX).with_app(bitcoin()));
+        device.set_plugged(false);
+        let (gui, client) = start_client(device.clone(), manager_api(String::new()));
+
+        // Plugged in, but locked.
+        device.set_plugged(true);
+        client.send(LedgerMessage::TryConnect).await.unwrap();
+        assert_eq!(next_state(&gui).await, DeviceState::Locked);
+
+        device.set_locked(false);
+        client.send(LedgerMessage::TryConnect).await.unwrap();
+        let session = next_session(&gui).await;
+        assert_eq!(session.model, Model::NanoX);
+        assert_eq!(session.version, "2.2.3");
+        assert!(session.onboarded);
+        assert_eq!(
+            version(&session, &AppId::bitcoin()),
+            Version::Installed("2.2.2".to_string())
+        );
+        assert_eq!(
+            version(&session, &AppId::bitcoin_test()),
+            Version::NotInstalled
+        );
+
+        open_app(&*device, &AppId::bitcoin()).unwrap();
+        assert_eq!(device.open_app().as_deref(), Some("Bitcoin"));
+        client.send(LedgerMessage::TryConnect).await.unwrap();
+        assert_eq!(
+            next_state(&gui).await,
+            DeviceState::AppOpen("Bitcoin".to_string())
+        );
+
+        device.quit_app();
+        client.send(LedgerMessage::TryConnect).await.unwrap();
+        next_session(&gui).await;
+
+        device.set_plugged(false);
+        client.send(LedgerMessage::TryConnect).await.unwrap();
+        assert_eq!(next_state(&gui).await, DeviceState::Disconnected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn poll_states() {
+        let poll = |device: &Arc<MockDevice>| {
+            let context = context(
+                TransportConfig::Mock(device.clone()),
+                manager_api(String::new()),
+            );
+            tokio::task::spawn_blocking(move || context.poll(None))
+        };
+
+        let device = Arc::new(MockDevice::new(Model::Stax));
+        device.set_onboarded(false);
+        match poll(&device).await.unwrap() {
+            DeviceState::Dashboard(session) => {
+                assert_eq!(session.model, Model::Stax);
+                assert!(!session.onboarded);
+            }
+            state => panic!("Expected the dashboard, got {:?}", state),
+        }
+
+        device.set_onboarded(true);
+        device.set_refuse(true);
+        assert_eq!(
+            poll(&device).await.unwrap(),
+            DeviceState::Error(LedgerError::UserRefused(0x5501))
+        );
+
+        device.set_refuse(false);
+        device.set_bootloader(true);
+        assert_eq!(
+            poll(&device).await.unwrap(),
+            DeviceState::Bootloader("1.16".to_string())
+        );
+
+        device.set_bootloader(false);
+        device.set_version("2.2.4-osu");
+        assert_eq!(
+            poll(&device).await.unwrap(),
+            DeviceState::OsUpdater("2.2.4".to_string())
+        );
+
+        device.set_version("2.2.3");
+        device.push_status(0x6f00);
+        assert_eq!(
+            poll(&device).await.unwrap(),
+            DeviceState::Error(LedgerError::Device(0x6f00))
+        );
+    }
+
+    #[tokio::test]
+    async fn polling() {
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        let (sender, _) = async_channel::unbounded();
+        let (loopback, receiver) = async_channel::unbounded();
+        let mut client = LedgerClient::new(sender, receiver, loopback)
+            .with_transport(TransportConfig::Mock(device));
+        assert!(client.needs_polling());
+
+        client.state = DeviceState::Error(LedgerError::Device(0x6f00));
+        assert!(client.needs_polling());
+        for e in [LedgerError::Cancelled, LedgerError::UserRefused(0x5501)] {
+            client.state = DeviceState::Error(e);
+            assert!(!client.needs_polling());
+        }
+    }
+
+    fn ledger_device(path: &str) -> LedgerDevice {
+        LedgerDevice {
+            path: path.to_string(),
+            product_id: 0x4011,
+            model: Model::NanoX,
+            product: None,
+            serial_number: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn select_device() {
+        let (first, second) = (ledger_device("first"), ledger_device("second"));
+        let (sender, _) = async_channel::unbounded();
+        let (loopback, receiver) = async_channel::unbounded();
+        let mut client = LedgerClient::new(sender, receiver, loopback).with_transport(
+            TransportConfig::Mock(Arc::new(MockDevice::new(Model::NanoX))),
+        );
+        client.devices = vec![first.clone(), second.clone()];
+        client.device.selected = Some(first.clone());
+
+        // The first device is being polled when the second one is picked.
+        client.current = Some(Operation::Poll);
+        client.handle_message(LedgerMessage::SelectDevice(second.clone()));
+        assert_eq!(client.device.selected, Some(second.clone()));
+        assert_eq!(client.state, DeviceState::Disconnected);
+        assert!(client.pending.is_empty());
+
+        // Its state is dropped, and the second device polled in its place.
+        client.current = None;
+        client.handle_result(OperationResult::Polled(
+            Some(first.clone()),
+            DeviceState::Locked,
+        ));
+        assert_eq!(client.state, DeviceState::Disconnected);
+        assert!(matches!(client.current, Some(Operation::Poll)));
+
+        // The device is kept while an app is written to it.
+        client.current = Some(Operation::Install(AppId::bitcoin()));
+        client.handle_message(LedgerMessage::SelectDevice(first));
+        assert_eq!(client.device.selected, Some(second));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replay_poll() {
+        let transcript = Transcript::parse(include_str!(
+            "../fixtures/nanox-2.2.3-dashboard.synthetic.jsonl"
+        ))
+        .unwrap();
+        let transport = TransportConfig::Replay(Arc::new(ReplayTransport::new(transcript)));
+        let context = context(transport, manager_api(String::new()));
+        let state = tokio::task::spawn_blocking(move || context.poll(None))
+            .await
+            .unwrap();
+        let session = match state {
+            DeviceState::Dashboard(session) => session,
+            state => panic!("Expected the dashboard, got {:?}", state),
+        };
+        assert_eq!(
+            (session.model, session.version.as_str()),
+            (Model::NanoX, "2.2.3")
+        );
+        for app in ["Bitcoin", "Bitcoin Test", "Ethereum"] {
+            assert!(version(&session, &AppId(app.to_string())).is_installed());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn install_app() {
+        let hsm =
+            MockHsm::start(HsmScript::install("Bitcoin", BITCOIN_HASH, BITCOIN_BLOCKS)).unwrap();
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        let (gui, client) = start_client(device.clone(), manager_api(hsm.url()));
+        let session = next_session(&gui).await;
+        assert_eq!(version(&session, &AppId::bitcoin()), Version::NotInstalled);
+
+        client
+            .send(LedgerMessage::Install(AppId::bitcoin()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_state(&gui).await,
+            DeviceState::Installing("Bitcoin".to_string())
+        );
+        let outcome = expect(&gui, |msg| match msg {
+            LedgerMessage::InstallOutcome(app, outcome) => Some((app, outcome)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(outcome, (AppId::bitcoin(), InstallOutcome::Installed));
+        let session = next_session(&gui).await;
+        assert_eq!(
+            version(&session, &AppId::bitcoin()),
+            Version::Installed("2.2.2".to_string())
+        );
+        assert_eq!(device.apps()[0].hash, BITCOIN_HASH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn uninstall_app() {
+        let hsm = MockHsm::start(HsmScript::uninstall("Bitcoin")).unwrap();
+        let device = Arc::new(MockDevice::new(Model::NanoX).with_app(bitcoin()));
+        let (gui, client) = start_client(device.clone(), manager_api(hsm.url()));
+        let session = next_session(&gui).await;
+        assert!(version(&session, &AppId::bitcoin()).is_installed());
+
+        client
+            .send(LedgerMessage::Uninstall(AppId::bitcoin()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_state(&gui).await,
+            DeviceState::Uninstalling("Bitcoin".to_string())
+        );
+        let outcome = expect(&gui, |msg| match msg {
+            LedgerMessage::UninstallOutcome(app, outcome) => Some((app, outcome)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(outcome, (AppId::bitcoin(), UninstallOutcome::Uninstalled));
+        let session = next_session(&gui).await;
+        assert_eq!(version(&session, &AppId::bitcoin()), Version::NotInstalled);
+        assert!(device.apps().is_empty());
+    }
+
+    /// Bitcoin, and Bitcoin Test which depends on it.
+    fn bitcoin_catalog() -> Vec<CatalogApp> {
+        serde_json::from_value(serde_json::json!([
+            {"versionName": "Bitcoin", "perso": "", "delete": "", "deleteKey": "",
+             "firmware": "", "firmwareKey": "", "hash": ""},
+            {"versionName": "Bitcoin Test", "parentName": "Bitcoin", "perso": "", "delete": "",
+             "deleteKey": "", "firmware": "", "firmwareKey": "", "hash": ""},
+        ]))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_dependency() {
+        let (sender, gui) = async_channel::unbounded();
+        let (loopback, receiver) = async_channel::unbounded();
+        let mut client = LedgerClient::new(sender, receiver, loopback).with_transport(
+            TransportConfig::Mock(Arc::new(MockDevice::new(Model::NanoX))),
+        );
+        client.catalog = bitcoin_catalog();
+        client.current = Some(Operation::Poll);
+        // Bitcoin is queued first, Bitcoin Test is left waiting for it.
+        client.handle_message(LedgerMessage::Install(AppId::bitcoin_test()));
+        assert_eq!(client.pending.len(), 2);
+        client.pending.pop_front();
+
+        client.current = None;
+        let error = LedgerError::Device(0x6f00);
+        client.handle_result(OperationResult::Installed(
+            AppId::bitcoin(),
+            InstallOutcome::Failed(error.clone()),
+        ));
+        assert!(matches!(client.current, Some(Operation::Poll)));
+        assert!(client.pending.is_empty());
+        let outcomes: Vec<_> = std::iter::from_fn(|| gui.try_recv().ok())
+            .filter_map(|msg| match msg {
+                LedgerMessage::InstallOutcome(app, outcome) => Some((app, outcome)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (AppId::bitcoin(), InstallOutcome::Failed(error)),
+                (
+                    AppId::bitcoin_test(),
+                    InstallOutcome::Failed(LedgerError::MissingDependency("Bitcoin".to_string()))
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_queued() {
+        let (sender, gui) = async_channel::unbounded();
+        let (loopback, receiver) = async_channel::unbounded();
+        let mut client = LedgerClient::new(sender, receiver, loopback).with_transport(
+            TransportConfig::Mock(Arc::new(MockDevice::new(Model::NanoX))),
+        );
+        client.current = Some(Operation::Poll);
+        client.handle_message(LedgerMessage::Install(AppId::bitcoin()));
+        client.handle_message(LedgerMessage::Uninstall(AppId::bitcoin_test()));
+
+        // Nothing ran, the GUI still hears how they ended.
+        client.handle_message(LedgerMessage::Cancel);
+        assert!(client.pending.is_empty());
+        let outcomes: Vec<_> = std::iter::from_fn(|| gui.try_recv().ok()).collect();
+        assert!(matches!(
+            outcomes.as_slice(),
+            [
+                LedgerMessage::InstallOutcome(_, InstallOutcome::Failed(LedgerError::Cancelled)),
+                LedgerMessage::UninstallOutcome(
+                    _,
+                    UninstallOutcome::Failed(LedgerError::Cancelled)
+                ),
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn uninstall_dependencies() {
+        let (sender, gui) = async_channel::unbounded();
+        let (loopback, receiver) = async_channel::unbounded();
+        let mut client = LedgerClient::new(sender, receiver, loopback).with_transport(
+            TransportConfig::Mock(Arc::new(MockDevice::new(Model::NanoX))),
+        );
+        let installed = Version::Installed("2.2.2".to_string());
+        client.state = DeviceState::Dashboard(DeviceSession {
+            target_id: 0x33000004,
+            model: Model::NanoX,
+            version: "2.2.3".to_string(),
+            onboarded: true,
+            apps: vec![
+                (AppId::bitcoin(), installed.clone()),
+                (AppId::bitcoin_test(), installed),
+            ],
+        });
+        client.catalog = bitcoin_catalog();
+
+        // Bitcoin Test runs on top of Bitcoin, which must stay.
+        client.handle_message(LedgerMessage::Uninstall(AppId::bitcoin()));
+        assert!(client.current.is_none() && client.pending.is_empty());
+        match gui.try_recv() {
+            Ok(LedgerMessage::UninstallOutcome(app, UninstallOutcome::Failed(e))) => {
+                assert_eq!(app, AppId::bitcoin());
+                assert_eq!(e, LedgerError::RequiredBy(vec!["Bitcoin Test".to_string()]));
+            }
+            msg => panic!("Expected the uninstall to fail, got {:?}", msg),
+        }
+
+        client.handle_message(LedgerMessage::Uninstall(AppId::bitcoin_test()));
+        assert!(matches!(client.current, Some(Operation::Uninstall(_))));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn install_checks() {
+        let device = Arc::new(MockDevice::new(Model::NanoX).with_app(bitcoin()));
+        assert_eq!(install(device).await, Ok(InstallOutcome::AlreadyInstalled));
+
+        let other_build = MockApp::new("Bitcoin", [0x01; 32], BITCOIN_BLOCKS);
+        let device = Arc::new(MockDevice::new(Model::NanoX).with_app(other_build));
+        assert_eq!(
+            install(device).await,
+            Err(LedgerError::AppAlreadyInstalled("Bitcoin".to_string()))
+        );
+
+        // 2 MiB in blocks of 4 KiB.
+        let big = MockApp::new("Big", [0x02; 32], 500);
+        let device = Arc::new(MockDevice::new(Model::NanoX).with_app(big));
+        assert_eq!(
+            install(device).await,
+            Err(LedgerError::InsufficientStorage(20 * 4096, 12 * 4096))
+        );
+
+        // The manager has no app for this firmware.
+        let stub = StubBackend::default();
+        stub.respond("/apps/by-target", 200, "[]");
+        let no_apps = ManagerApi::default().with_http_backend(Arc::new(stub));
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        device.set_version("2.2.4");
+        assert_eq!(
+            install_with(device, no_apps).await,
+            Err(LedgerError::UnsupportedFirmware("2.2.4".to_string()))
+        );
+
+        // The build served for 2.2.4 is made against 2.2.3, that's the manager's call.
+        let device = Arc::new(MockDevice::new(Model::NanoX).with_app(bitcoin()));
+        device.set_version("2.2.4");
+        assert_eq!(install(device).await, Ok(InstallOutcome::AlreadyInstalled));
+
+        let device = Arc::new(MockDevice::new(Model::NanoX).with_app(bitcoin()));
+        open_app(&*device, &AppId::bitcoin()).unwrap();
+        assert_eq!(
+            install(device).await,
+            Err(LedgerError::AppOpen("Bitcoin".to_string()))
+        );
+
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        device.set_locked(true);
+        assert_eq!(
+            install(device).await,
+            Err(LedgerError::DeviceLocked(0x5515))
+        );
+    }
+}
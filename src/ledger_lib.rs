@@ -1,8 +1,28 @@
 use ledger_apdu::APDUCommand;
-use ledger_transport_hidapi::TransportNativeHID;
 use serde_derive::Deserialize;
 
-use std::{error, str};
+use crate::{
+    error::LedgerError,
+    manager_api::AppId,
+    model::Model,
+    transport::{AsyncExchange, Exchange, Transport},
+};
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use std::{
+    future::Future,
+    str,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::Notify,
+    time::{timeout, Instant},
+};
+use tungstenite::{Error as WsError, Message};
 
 // https://github.com/LedgerHQ/ledger-live/blob/dd1d17fd3ce7ed42558204b2f93707fb9b1599de/libs/device-core/src/commands/use-cases/getVersion.ts#L6
 const GET_VERSION_COMMAND: APDUCommand<&[u8]> = APDUCommand {
@@ -40,55 +60,232 @@ const OPEN_APP_COMMAND_TEMPLATE: APDUCommand<&[u8]> = APDUCommand {
     data: &[],
 };
 
-#[allow(unused)]
-pub const LIVE_COMMON_VERSION: &str = "34.0.0";
-pub const PROVIDER: u32 = 1; // TODO: make it possible to set it.
-#[allow(unused)]
-pub const BASE_API_V1_URL: &str = "https://manager.api.live.ledger.com/api";
-pub const BASE_API_V2_URL: &str = "https://manager.api.live.ledger.com/api/v2";
-pub const BASE_SOCKET_URL: &str = "wss://scriptrunner.api.live.ledger.com/update";
+// https://github.com/LedgerHQ/ledger-live/blob/dd1d17fd3ce7ed42558204b2f93707fb9b1599de/libs/ledger-live-common/src/hw/getAppAndVersion.ts
+const GET_APP_AND_VERSION_COMMAND: APDUCommand<&[u8]> = APDUCommand {
+    cla: 0xb0,
+    ins: 0x01,
+    p1: 0x00,
+    p2: 0x00,
+    data: &[],
+};
+
+/// Name returned by `GET_APP_AND_VERSION` when no app is running.
+pub const DASHBOARD_NAME: &str = "BOLOS";
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum StatusCode {
-    //ACCESS_CONDITION_NOT_FULFILLED = 0x9804,
-    //ALGORITHM_NOT_SUPPORTED = 0x9484,
-    //CLA_NOT_SUPPORTED = 0x6e00,
-    //CODE_BLOCKED = 0x9840,
-    //CODE_NOT_INITIALIZED = 0x9802,
-    //COMMAND_INCOMPATIBLE_FILE_STRUCTURE = 0x6981,
-    //CONDITIONS_OF_USE_NOT_SATISFIED = 0x6985,
-    //CONTRADICTION_INVALIDATION = 0x9810,
-    //CONTRADICTION_SECRET_CODE_STATUS = 0x9808,
-    //CUSTOM_IMAGE_BOOTLOADER = 0x662f,
-    //CUSTOM_IMAGE_EMPTY = 0x662e,
-    //FILE_ALREADY_EXISTS = 0x6a89,
-    //FILE_NOT_FOUND = 0x9404,
-    //GP_AUTH_FAILED = 0x6300,
-    //HALTED = 0x6faa,
-    //INCONSISTENT_FILE = 0x9408,
-    //INCORRECT_DATA = 0x6a80,
-    //INCORRECT_LENGTH = 0x6700,
-    //INCORRECT_P1_P2 = 0x6b00,
-    //INS_NOT_SUPPORTED = 0x6d00,
-    //DEVICE_NOT_ONBOARDED = 0x6d07,
-    //DEVICE_NOT_ONBOARDED_2 = 0x6611,
-    //INVALID_KCV = 0x9485,
-    //INVALID_OFFSET = 0x9402,
-    //LICENSING = 0x6f42,
-    //LOCKED_DEVICE = 0x5515,
-    //MAX_VALUE_REACHED = 0x9850,
-    //MEMORY_PROBLEM = 0x9240,
-    //MISSING_CRITICAL_PARAMETER = 0x6800,
-    //NO_EF_SELECTED = 0x9400,
-    //NOT_ENOUGH_MEMORY_SPACE = 0x6a84,
+    ACCESS_CONDITION_NOT_FULFILLED = 0x9804,
+    ALGORITHM_NOT_SUPPORTED = 0x9484,
+    CLA_NOT_SUPPORTED = 0x6e00,
+    CODE_BLOCKED = 0x9840,
+    CODE_NOT_INITIALIZED = 0x9802,
+    COMMAND_INCOMPATIBLE_FILE_STRUCTURE = 0x6981,
+    CONDITIONS_OF_USE_NOT_SATISFIED = 0x6985,
+    CONTRADICTION_INVALIDATION = 0x9810,
+    CONTRADICTION_SECRET_CODE_STATUS = 0x9808,
+    CUSTOM_IMAGE_BOOTLOADER = 0x662f,
+    CUSTOM_IMAGE_EMPTY = 0x662e,
+    FILE_ALREADY_EXISTS = 0x6a89,
+    FILE_NOT_FOUND = 0x9404,
+    GP_AUTH_FAILED = 0x6300,
+    HALTED = 0x6faa,
+    INCONSISTENT_FILE = 0x9408,
+    INCORRECT_DATA = 0x6a80,
+    INCORRECT_LENGTH = 0x6700,
+    INCORRECT_P1_P2 = 0x6b00,
+    INS_NOT_SUPPORTED = 0x6d00,
+    DEVICE_NOT_ONBOARDED = 0x6d07,
+    DEVICE_NOT_ONBOARDED_2 = 0x6611,
+    INVALID_KCV = 0x9485,
+    INVALID_OFFSET = 0x9402,
+    LICENSING = 0x6f42,
+    LOCKED_DEVICE = 0x5515,
+    MAX_VALUE_REACHED = 0x9850,
+    MEMORY_PROBLEM = 0x9240,
+    MISSING_CRITICAL_PARAMETER = 0x6800,
+    NO_EF_SELECTED = 0x9400,
+    NOT_ENOUGH_MEMORY_SPACE = 0x6a84,
     OK = 0x9000,
-    //PIN_REMAINING_ATTEMPTS = 0x63c0,
-    //REFERENCED_DATA_NOT_FOUND = 0x6a88,
-    //SECURITY_STATUS_NOT_SATISFIED = 0x6982,
-    //TECHNICAL_PROBLEM = 0x6f00,
-    //UNKNOWN_APDU = 0x6d02,
-    //USER_REFUSED_ON_DEVICE = 0x5501,
-    //NOT_ENOUGH_SPACE = 0x5102,
+    PIN_REMAINING_ATTEMPTS = 0x63c0,
+    REFERENCED_DATA_NOT_FOUND = 0x6a88,
+    SECURITY_STATUS_NOT_SATISFIED = 0x6982,
+    TECHNICAL_PROBLEM = 0x6f00,
+    UNKNOWN_APDU = 0x6d02,
+    USER_REFUSED_ON_DEVICE = 0x5501,
+    NOT_ENOUGH_SPACE = 0x5102,
+}
+
+impl StatusCode {
+    /// Look up a status word, `None` if it's not a known Ledger one.
+    pub fn from_u16(code: u16) -> Option<Self> {
+        Some(match code {
+            0x9804 => StatusCode::ACCESS_CONDITION_NOT_FULFILLED,
+            0x9484 => StatusCode::ALGORITHM_NOT_SUPPORTED,
+            0x6e00 => StatusCode::CLA_NOT_SUPPORTED,
+            0x9840 => StatusCode::CODE_BLOCKED,
+            0x9802 => StatusCode::CODE_NOT_INITIALIZED,
+            0x6981 => StatusCode::COMMAND_INCOMPATIBLE_FILE_STRUCTURE,
+            0x6985 => StatusCode::CONDITIONS_OF_USE_NOT_SATISFIED,
+            0x9810 => StatusCode::CONTRADICTION_INVALIDATION,
+            0x9808 => StatusCode::CONTRADICTION_SECRET_CODE_STATUS,
+            0x662f => StatusCode::CUSTOM_IMAGE_BOOTLOADER,
+            0x662e => StatusCode::CUSTOM_IMAGE_EMPTY,
+            0x6a89 => StatusCode::FILE_ALREADY_EXISTS,
+            0x9404 => StatusCode::FILE_NOT_FOUND,
+            0x6300 => StatusCode::GP_AUTH_FAILED,
+            0x6faa => StatusCode::HALTED,
+            0x9408 => StatusCode::INCONSISTENT_FILE,
+            0x6a80 => StatusCode::INCORRECT_DATA,
+            0x6700 => StatusCode::INCORRECT_LENGTH,
+            0x6b00 => StatusCode::INCORRECT_P1_P2,
+            0x6d00 => StatusCode::INS_NOT_SUPPORTED,
+            0x6d07 => StatusCode::DEVICE_NOT_ONBOARDED,
+            0x6611 => StatusCode::DEVICE_NOT_ONBOARDED_2,
+            0x9485 => StatusCode::INVALID_KCV,
+            0x9402 => StatusCode::INVALID_OFFSET,
+            0x6f42 => StatusCode::LICENSING,
+            0x5515 => StatusCode::LOCKED_DEVICE,
+            0x9850 => StatusCode::MAX_VALUE_REACHED,
+            0x9240 => StatusCode::MEMORY_PROBLEM,
+            0x6800 => StatusCode::MISSING_CRITICAL_PARAMETER,
+            0x9400 => StatusCode::NO_EF_SELECTED,
+            0x6a84 => StatusCode::NOT_ENOUGH_MEMORY_SPACE,
+            0x9000 => StatusCode::OK,
+            0x63c0 => StatusCode::PIN_REMAINING_ATTEMPTS,
+            0x6a88 => StatusCode::REFERENCED_DATA_NOT_FOUND,
+            0x6982 => StatusCode::SECURITY_STATUS_NOT_SATISFIED,
+            0x6f00 => StatusCode::TECHNICAL_PROBLEM,
+            0x6d02 => StatusCode::UNKNOWN_APDU,
+            0x5501 => StatusCode::USER_REFUSED_ON_DEVICE,
+            0x5102 => StatusCode::NOT_ENOUGH_SPACE,
+            _ => return None,
+        })
+    }
+
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            StatusCode::ACCESS_CONDITION_NOT_FULFILLED => "Access condition not fulfilled.",
+            StatusCode::ALGORITHM_NOT_SUPPORTED => "Algorithm not supported.",
+            StatusCode::CLA_NOT_SUPPORTED => "Command class not supported.",
+            StatusCode::CODE_BLOCKED => "PIN code blocked.",
+            StatusCode::CODE_NOT_INITIALIZED => "PIN code not initialized.",
+            StatusCode::COMMAND_INCOMPATIBLE_FILE_STRUCTURE => {
+                "Command incompatible with file structure."
+            }
+            StatusCode::CONDITIONS_OF_USE_NOT_SATISFIED => "Conditions of use not satisfied.",
+            StatusCode::CONTRADICTION_INVALIDATION => "Contradiction invalidation.",
+            StatusCode::CONTRADICTION_SECRET_CODE_STATUS => "Contradiction in secret code status.",
+            StatusCode::CUSTOM_IMAGE_BOOTLOADER => {
+                "Custom lock screen image not available in bootloader mode."
+            }
+            StatusCode::CUSTOM_IMAGE_EMPTY => "No custom lock screen image.",
+            StatusCode::FILE_ALREADY_EXISTS => "The app is already installed.",
+            StatusCode::FILE_NOT_FOUND => "The app is not installed.",
+            StatusCode::GP_AUTH_FAILED => "Authentication with Ledger's HSM failed.",
+            StatusCode::HALTED => "The device halted.",
+            StatusCode::INCONSISTENT_FILE => "Inconsistent file.",
+            StatusCode::INCORRECT_DATA => "Incorrect data.",
+            StatusCode::INCORRECT_LENGTH => "Incorrect length.",
+            StatusCode::INCORRECT_P1_P2 => "Incorrect parameters.",
+            StatusCode::INS_NOT_SUPPORTED => "Instruction not supported.",
+            StatusCode::DEVICE_NOT_ONBOARDED => "The device is not set up.",
+            StatusCode::DEVICE_NOT_ONBOARDED_2 => "The device is not set up.",
+            StatusCode::INVALID_KCV => "Invalid key check value.",
+            StatusCode::INVALID_OFFSET => "Invalid offset.",
+            StatusCode::LICENSING => "Licensing error.",
+            StatusCode::LOCKED_DEVICE => "The device is locked.",
+            StatusCode::MAX_VALUE_REACHED => "Maximum value reached.",
+            StatusCode::MEMORY_PROBLEM => "Memory problem.",
+            StatusCode::MISSING_CRITICAL_PARAMETER => "Missing critical parameter.",
+            StatusCode::NO_EF_SELECTED => "No file selected.",
+            StatusCode::NOT_ENOUGH_MEMORY_SPACE => "Not enough memory space on the device.",
+            StatusCode::OK => "Success.",
+            StatusCode::PIN_REMAINING_ATTEMPTS => "Wrong PIN.",
+            StatusCode::REFERENCED_DATA_NOT_FOUND => "Referenced data not found.",
+            StatusCode::SECURITY_STATUS_NOT_SATISFIED => "Security status not satisfied.",
+            StatusCode::TECHNICAL_PROBLEM => "Technical problem on the device.",
+            StatusCode::UNKNOWN_APDU => "Unknown command.",
+            StatusCode::USER_REFUSED_ON_DEVICE => "The action was refused on the device.",
+            StatusCode::NOT_ENOUGH_SPACE => "Not enough space on the device.",
+        }
+    }
+
+    /// What the user can do about it, if anything.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            StatusCode::CLA_NOT_SUPPORTED
+            | StatusCode::INS_NOT_SUPPORTED
+            | StatusCode::UNKNOWN_APDU => {
+                Some("Close the app running on the device and go back to the dashboard.")
+            }
+            StatusCode::CODE_NOT_INITIALIZED
+            | StatusCode::DEVICE_NOT_ONBOARDED
+            | StatusCode::DEVICE_NOT_ONBOARDED_2 => {
+                Some("Set up the device before installing apps.")
+            }
+            StatusCode::CONDITIONS_OF_USE_NOT_SATISFIED => {
+                Some("The action was probably refused on the device.")
+            }
+            StatusCode::GP_AUTH_FAILED => Some("Check the device is genuine and retry."),
+            StatusCode::HALTED | StatusCode::TECHNICAL_PROBLEM => {
+                Some("Unplug and replug the device, then retry.")
+            }
+            StatusCode::LOCKED_DEVICE | StatusCode::SECURITY_STATUS_NOT_SATISFIED => {
+                Some("Unlock the device with its PIN.")
+            }
+            StatusCode::NOT_ENOUGH_MEMORY_SPACE | StatusCode::NOT_ENOUGH_SPACE => {
+                Some("Uninstall some apps to free space.")
+            }
+            _ => None,
+        }
+    }
+
+    /// Human readable description of any status word, known or not.
+    pub fn describe(code: u16) -> String {
+        match StatusCode::from_u16(code) {
+            Some(status) => match status.hint() {
+                Some(hint) => format!("{} {} ({:#06x})", status.explanation(), hint, code),
+                None => format!("{} ({:#06x})", status.explanation(), code),
+            },
+            None => format!("Unknown status word {:#06x}.", code),
+        }
+    }
+}
+
+// Bits of the first byte of the flags returned by GET_VERSION.
+// https://github.com/LedgerHQ/ledger-live/blob/dd1d17fd3ce7ed42558204b2f93707fb9b1599de/libs/ledger-live-common/src/hw/getDeviceInfo.ts
+const FLAG_RECOVERY_MODE: u8 = 0x01;
+const FLAG_ONBOARDED: u8 = 0x04;
+const FLAG_MANAGER_ALLOWED: u8 = 0x08;
+const FLAG_PIN_VALIDATED: u8 = 0x80;
+
+/// Suffix of the version of a device running the OS updater.
+const OSU_SUFFIX: &str = "-osu";
+
+/// The device status, decoded from the flags returned by `GET_VERSION`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceStatus {
+    /// The device has been set up (seed and PIN).
+    pub onboarded: bool,
+    pub pin_validated: bool,
+    /// The user already allowed Ledger manager during this session.
+    pub manager_allowed: bool,
+    pub recovery_mode: bool,
+}
+
+impl DeviceStatus {
+    pub fn from_flags(flags: &[u8]) -> Self {
+        let flag = flags.first().copied().unwrap_or(0);
+        // Older firmwares don't report the onboarding, only devices set up can answer then.
+        let detailed = flags.len() == 4;
+        Self {
+            onboarded: !detailed || flag & FLAG_ONBOARDED != 0,
+            pin_validated: flag & FLAG_PIN_VALIDATED != 0,
+            manager_allowed: flag & FLAG_MANAGER_ALLOWED != 0,
+            recovery_mode: detailed && flag & FLAG_RECOVERY_MODE != 0,
+        }
+    }
 }
 
 // NOTE: MCU target id is always == target_id in Ledger Live
@@ -96,8 +293,14 @@ pub enum StatusCode {
 #[allow(dead_code)]
 pub struct DeviceInfo {
     pub target_id: u32,
+    /// The firmware version, without the `-osu` suffix.
     pub version: String,
+    /// The device runs the OS updater, in the middle of a firmware update.
+    pub is_osu: bool,
     pub flags: Vec<u8>,
+    pub status: DeviceStatus,
+    /// Language of the device interface, only reported by recent firmwares.
+    pub language_id: Option<u32>,
     pub is_bootloader: bool,
     pub se_version: Option<String>,
     pub se_target_id: u32,
@@ -105,133 +308,242 @@ pub struct DeviceInfo {
 }
 
 impl DeviceInfo {
+    /// The device model, known right after `GET_VERSION`.
+    pub fn model(&self) -> Model {
+        Model::from_target_id(self.target_id)
+    }
+
     /// Query information about this device.
-    ///
-    /// Adapted from https://github.com/LedgerHQ/ledger-live/blob/dd1d17fd3ce7ed42558204b2f93707fb9b1599de/libs/device-core/src/commands/use-cases/parseGetVersionResponse.ts
-    pub fn new(ledger_api: &TransportNativeHID) -> Result<Self, Box<dyn error::Error>> {
+    pub fn new(ledger_api: &dyn Transport) -> Result<Self, LedgerError> {
         let ver_answer = ledger_api.exchange(&GET_VERSION_COMMAND)?;
-        let data = ver_answer.data();
-        let mut i = 0;
-
-        if data.len() < 5 {
-            return Err("Not enough data".into());
+        if let Some(e) = LedgerError::from_status(ver_answer.retcode()) {
+            return Err(e);
         }
-        let target_id = u32::from_be_bytes(data[i..i + 4].try_into()?);
-        i += 4;
-        let raw_ver_len = data[i] as usize;
-        i += 1;
+        Ok(parse_get_version(ver_answer.data())?)
+    }
+}
 
-        if data.len() < i + raw_ver_len + 1 {
-            return Err("Not enough data".into());
-        }
-        let raw_ver = &data[i..i + raw_ver_len];
-        i += raw_ver_len;
-        let version = str::from_utf8(raw_ver)?;
-        let flags_len = data[i] as usize;
-        i += 1;
-
-        if data.len() < i + flags_len {
-            return Err("Not enough data".into());
-        }
-        let flags = &data[i..i + flags_len];
-        i += flags_len;
+/// The app currently running on the device, [`DASHBOARD_NAME`] if none.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct RunningApp {
+    pub name: String,
+    pub version: String,
+}
 
-        let is_bootloader = (target_id & 4026531840) != 805306368;
-        Ok(if is_bootloader {
-            if data.len() < i + 1 {
-                return Err("Not enough data".into());
-            }
-            let part1_len = data[i] as usize;
-            i += 1;
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct InstalledApp {
+    pub name: String,
+    pub hash: Vec<u8>,
+    pub hash_code_data: Vec<u8>,
+    pub blocks: u16,
+    pub flags: u16,
+}
+
+/// Why an answer of the device could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The answer ends before the field starting at this offset.
+    NotEnoughData(usize),
+    /// The field starting at this offset is not valid UTF-8.
+    InvalidUtf8(usize),
+    /// The field starting at this offset does not have the expected length.
+    InvalidLength(usize),
+    /// The answer does not start with the expected format byte.
+    UnexpectedFormat(u8),
+}
 
-            if data.len() < i + part1_len {
-                return Err("Not enough data".into());
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::NotEnoughData(offset) => write!(f, "Not enough data at offset {}", offset),
+            ParseError::InvalidUtf8(offset) => write!(f, "Invalid UTF-8 at offset {}", offset),
+            ParseError::InvalidLength(offset) => {
+                write!(f, "Invalid field length at offset {}", offset)
+            }
+            ParseError::UnexpectedFormat(format) => {
+                write!(f, "Unexpected answer format: {:#04x}", format)
             }
-            let part1 = &data[i..i + part1_len];
-            i += part1_len;
+        }
+    }
+}
 
-            if part1_len >= 5 {
-                let se_version = str::from_utf8(part1).unwrap();
+impl std::error::Error for ParseError {}
 
-                if data.len() < i + 1 {
-                    return Err("Not enough data".into());
-                }
-                let part2_len = data[i] as usize;
-                i += 1;
+/// Cursor over an answer of the device, every read being bounds checked.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
 
-                if data.len() < i + part2_len {
-                    return Err("Not enough data".into());
-                }
-                let part2 = &data[i..i + part2_len];
-                //i += part2_len;
-                let se_target_id = u32::from_be_bytes(part2.try_into().unwrap());
-
-                Self {
-                    target_id,
-                    version: version.to_string(),
-                    flags: flags.to_vec(),
-                    is_bootloader,
-                    se_version: Some(se_version.to_string()),
-                    se_target_id,
-                    mcu_version: None,
-                }
-            } else {
-                let se_target_id = u32::from_be_bytes(part1.try_into().unwrap());
-
-                Self {
-                    target_id,
-                    version: version.to_string(),
-                    flags: flags.to_vec(),
-                    is_bootloader,
-                    se_version: None,
-                    se_target_id,
-                    mcu_version: None,
-                }
-            }
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let bytes = self
+            .data
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or(ParseError::NotEnoughData(self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ParseError> {
+        let offset = self.pos;
+        be_u16(self.bytes(2)?, offset)
+    }
+
+    fn u32(&mut self) -> Result<u32, ParseError> {
+        let offset = self.pos;
+        be_u32(self.bytes(4)?, offset)
+    }
+
+    /// A field prefixed by its length on one byte.
+    fn lv(&mut self) -> Result<&'a [u8], ParseError> {
+        let len = self.u8()? as usize;
+        self.bytes(len)
+    }
+
+    fn lv_str(&mut self) -> Result<&'a str, ParseError> {
+        let offset = self.pos;
+        utf8(self.lv()?, offset)
+    }
+}
+
+fn be_u16(bytes: &[u8], offset: usize) -> Result<u16, ParseError> {
+    bytes
+        .try_into()
+        .map(u16::from_be_bytes)
+        .map_err(|_| ParseError::InvalidLength(offset))
+}
+
+fn be_u32(bytes: &[u8], offset: usize) -> Result<u32, ParseError> {
+    bytes
+        .try_into()
+        .map(u32::from_be_bytes)
+        .map_err(|_| ParseError::InvalidLength(offset))
+}
+
+fn utf8(bytes: &[u8], offset: usize) -> Result<&str, ParseError> {
+    str::from_utf8(bytes).map_err(|_| ParseError::InvalidUtf8(offset))
+}
+
+/// Parse the data of a `GET_VERSION` answer, without its status word.
+///
+/// Adapted from https://github.com/LedgerHQ/ledger-live/blob/dd1d17fd3ce7ed42558204b2f93707fb9b1599de/libs/device-core/src/commands/use-cases/parseGetVersionResponse.ts
+pub fn parse_get_version(data: &[u8]) -> Result<DeviceInfo, ParseError> {
+    let mut reader = Reader::new(data);
+    let target_id = reader.u32()?;
+    let raw_version = reader.lv_str()?;
+    let is_osu = raw_version.contains(OSU_SUFFIX);
+    let version = raw_version.replace(OSU_SUFFIX, "");
+    let flags = reader.lv()?;
+
+    let is_bootloader = (target_id & 4026531840) != 805306368;
+    let mut info = DeviceInfo {
+        target_id,
+        version,
+        is_osu,
+        flags: flags.to_vec(),
+        status: DeviceStatus::from_flags(flags),
+        language_id: None,
+        is_bootloader,
+        se_version: None,
+        se_target_id: target_id,
+        mcu_version: None,
+    };
+
+    if is_bootloader {
+        let offset = reader.pos;
+        let part1 = reader.lv()?;
+        if part1.len() >= 5 {
+            info.se_version = Some(utf8(part1, offset)?.to_string());
+            let offset = reader.pos;
+            info.se_target_id = be_u32(reader.lv()?, offset)?;
         } else {
-            if data.len() < i + 1 {
-                return Err("Not enough data".into());
-            }
-            let mcu_len = data[i] as usize;
-            i += 1;
+            info.se_target_id = be_u32(part1, offset)?;
+        }
+    } else {
+        let offset = reader.pos;
+        let mcu = reader.lv()?;
+        let mcu = match mcu.split_last() {
+            Some((0, rest)) => rest,
+            _ => mcu,
+        };
+        info.mcu_version = Some(utf8(mcu, offset)?.to_string());
+        info.se_version = Some(info.version.clone());
 
-            if data.len() < i + mcu_len {
-                return Err("Not enough data".into());
+        // Recent firmwares append the MCU bootloader version, the hardware version and the
+        // language id, each one prefixed by its length.
+        let mut field = 0;
+        while !reader.is_empty() && field < 3 {
+            let value = reader.lv()?;
+            if field == 2 {
+                info.language_id = Some(value.iter().fold(0u32, |id, b| (id << 8) | *b as u32));
             }
-            let mcu = &data[i..i + mcu_len];
-            //i += mcu_len;
-            let mcu = if mcu[mcu.len() - 1] == 0 {
-                &mcu[..mcu.len() - 1]
-            } else {
-                mcu
-            };
-            let mcu_version = str::from_utf8(mcu).unwrap();
-
-            //let osu_str = b"-osu";
-            //if raw_ver.windows(osu_str.len()).any(|w| w == osu_str) {}
-            //TODO. See https://github.com/LedgerHQ/ledger-live/blob/dcbda65e65ead4014e767778da6022b78d8eddad/libs/ledgerjs/packages/devices/src/index.ts#L3-L156
-
-            Self {
-                target_id,
-                version: version.to_string(),
-                flags: flags.to_vec(),
-                is_bootloader,
-                se_version: Some(version.to_string()),
-                se_target_id: target_id,
-                mcu_version: Some(mcu_version.to_string()),
-            }
-        })
+            field += 1;
+        }
     }
+
+    Ok(info)
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-pub struct InstalledApp {
-    pub name: String,
-    pub hash: Vec<u8>,
-    pub hash_code_data: Vec<u8>,
-    pub blocks: u16,
-    pub flags: u16,
+/// Parse one page of a `LIST_APPS` answer, without its status word.
+///
+/// See https://github.com/LedgerHQ/ledger-live/blob/99879eb5bada1ecaea7a02d8886e16b44657af6d/libs/ledger-live-common/src/hw/listApps.ts#L9
+pub fn parse_list_apps_page(data: &[u8]) -> Result<Vec<InstalledApp>, ParseError> {
+    let mut reader = Reader::new(data);
+    let format = reader.u8()?;
+    if format != 0x01 {
+        return Err(ParseError::UnexpectedFormat(format));
+    }
+
+    let mut installed_apps = Vec::new();
+    while !reader.is_empty() {
+        let offset = reader.pos;
+        let len = reader.u8()? as usize;
+        let blocks = reader.u16()?;
+        let flags = reader.u16()?;
+        let hash_code_data = reader.bytes(32)?.to_vec();
+        let hash = reader.bytes(32)?.to_vec();
+        let name_offset = reader.pos;
+        let name = reader.lv()?;
+        if len != name.len() + 70 {
+            return Err(ParseError::InvalidLength(offset));
+        }
+
+        installed_apps.push(InstalledApp {
+            name: utf8(name, name_offset)?.to_string(),
+            hash,
+            hash_code_data,
+            blocks,
+            flags,
+        });
+    }
+
+    Ok(installed_apps)
+}
+
+/// Parse the data of a `GET_APP_AND_VERSION` answer, without its status word.
+pub fn parse_app_and_version(data: &[u8]) -> Result<RunningApp, ParseError> {
+    // format (1) | name length (1) | name | version length (1) | version | ...
+    let mut reader = Reader::new(data);
+    reader.u8()?;
+    let name = reader.lv_str()?.to_string();
+    let version = reader.lv_str()?.to_string();
+    Ok(RunningApp { name, version })
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -248,15 +560,193 @@ struct HsmMessage {
     pub data: Option<HsmMessageData>,
 }
 
-fn deser_apdu_command(hex_str: &str) -> Result<APDUCommand<Vec<u8>>, Box<dyn error::Error>> {
+/// The step an HSM session is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HsmPhase {
+    Connecting,
+    /// The HSM sends standalone commands, checking the device.
+    Exchange,
+    /// The HSM sent the list of commands writing the app, they are being sent to the device.
+    Bulk,
+    Done,
+}
+
+/// Progress of an HSM session, reported after each command sent to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HsmProgress {
+    pub phase: HsmPhase,
+    /// Number of commands of the bulk list already sent.
+    pub index: usize,
+    /// Number of commands in the bulk list, 0 until it's received.
+    pub total: usize,
+    /// Bytes sent to the device since the session started.
+    pub bytes_sent: usize,
+}
+
+impl HsmProgress {
+    /// Completion of the session, between 0 and 1. Only the bulk takes a noticeable time.
+    pub fn ratio(&self) -> f32 {
+        match self.phase {
+            HsmPhase::Connecting | HsmPhase::Exchange => 0.0,
+            HsmPhase::Bulk if self.total == 0 => 0.0,
+            HsmPhase::Bulk => self.index as f32 / self.total as f32,
+            HsmPhase::Done => 1.0,
+        }
+    }
+}
+
+/// Tell an operation to stop at the next safe point. An APDU exchange is never interrupted, the
+/// network requests and waiting for the HSM are, through [`CancelToken::run`].
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<CancelState>);
+
+#[derive(Debug, Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`LedgerError::Cancelled`] if the operation was cancelled.
+    pub fn check(&self) -> Result<(), LedgerError> {
+        if self.is_cancelled() {
+            Err(LedgerError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wait until the operation is cancelled.
+    pub async fn cancelled(&self) {
+        let notified = self.0.notify.notified();
+        tokio::pin!(notified);
+        // Registered before checking the flag, so a cancel in between isn't missed.
+        notified.as_mut().enable();
+        if !self.is_cancelled() {
+            notified.await;
+        }
+    }
+
+    /// Drive `future` to completion, unless the operation is cancelled first in which case it is
+    /// dropped and [`LedgerError::Cancelled`] is returned.
+    pub async fn run<F: Future>(&self, future: F) -> Result<F::Output, LedgerError> {
+        tokio::select! {
+            biased;
+            _ = self.cancelled() => Err(LedgerError::Cancelled),
+            output = future => Ok(output),
+        }
+    }
+}
+
+/// Send our close frame and wait for the HSM's one, so both ends know the session is over.
+async fn close_socket<S>(socket: &mut S, hsm_timeout: Duration)
+where
+    S: Stream<Item = Result<Message, WsError>> + Sink<Message, Error = WsError> + Unpin,
+{
+    let close = async {
+        socket.close().await?;
+        // The messages already sent by the HSM are dropped until its close frame.
+        while let Some(msg) = socket.next().await {
+            msg?;
+        }
+        Ok::<_, WsError>(())
+    };
+    match timeout(hsm_timeout, close).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::debug!("Fail to close the HSM socket: {}", e),
+        Err(_) => log::debug!("The HSM did not acknowledge the socket closing."),
+    }
+}
+
+/// Send a JSON message to the HSM.
+async fn send_hsm_message<S>(
+    socket: &mut S,
+    msg: serde_json::Value,
+    hsm_timeout: Duration,
+) -> Result<(), LedgerError>
+where
+    S: Sink<Message, Error = WsError> + Unpin,
+{
+    let text = serde_json::to_string(&msg)?;
+    timeout(hsm_timeout, socket.send(Message::Text(text)))
+        .await
+        .map_err(|_| LedgerError::Hsm("Timed out sending a message.".to_string()))??;
+    Ok(())
+}
+
+/// Wait for the next text message of the HSM, answering its pings. It's given up if none comes
+/// within `hsm_timeout`, even if the connection is still up: it's pinged half way to tell.
+async fn read_hsm_message<S>(
+    socket: &mut S,
+    hsm_timeout: Duration,
+    cancel: &CancelToken,
+) -> Result<String, LedgerError>
+where
+    S: Stream<Item = Result<Message, WsError>> + Sink<Message, Error = WsError> + Unpin,
+{
+    let start = Instant::now();
+    let mut pinged = false;
+    loop {
+        let silent = start.elapsed();
+        if silent >= hsm_timeout {
+            return Err(LedgerError::Hsm("The HSM stopped answering.".to_string()));
+        }
+        if !pinged && silent >= hsm_timeout / 2 {
+            log::debug!("No message from the HSM for {:?}, pinging it.", silent);
+            socket.send(Message::Ping(Vec::new())).await?;
+            pinged = true;
+        }
+        let wait = if pinged { hsm_timeout } else { hsm_timeout / 2 } - silent;
+        let msg = match cancel.run(timeout(wait, socket.next())).await? {
+            Ok(Some(msg)) => msg?,
+            Ok(None) => {
+                return Err(LedgerError::Hsm(
+                    "The connection was closed unexpectedly.".to_string(),
+                ))
+            }
+            Err(_) => continue,
+        };
+        match msg {
+            // It appears they only exchange JSON text messages.
+            Message::Text(text) => return Ok(text),
+            // tungstenite queues the pong, flush it so the HSM gets it now.
+            Message::Ping(_) => socket.flush().await?,
+            // Only a message of the HSM resets the timeout.
+            Message::Pong(_) => log::debug!("The HSM connection is up, waiting for a message."),
+            Message::Close(frame) => {
+                return Err(LedgerError::Hsm(format!(
+                    "The connection was closed by the HSM: {:?}.",
+                    frame
+                )))
+            }
+            msg => {
+                return Err(LedgerError::Hsm(format!(
+                    "Got an unsupported message type on the ws. Message: {:?}.",
+                    msg
+                )))
+            }
+        }
+    }
+}
+
+fn deser_apdu_command(hex_str: &str) -> Result<APDUCommand<Vec<u8>>, LedgerError> {
     let bytes = hex::decode(hex_str)?;
     if bytes.len() < 5 {
-        return Err("Invalid command".into());
+        return Err(LedgerError::Hsm(format!("Invalid command: {}", hex_str)));
     }
 
     let (cla, ins, p1, p2, data_len) = (bytes[0], bytes[1], bytes[2], bytes[3], bytes[4] as usize);
     if bytes.len() != 5 + data_len {
-        return Err("Invalid command".into());
+        return Err(LedgerError::Hsm(format!("Invalid command: {}", hex_str)));
     }
 
     Ok(APDUCommand {
@@ -272,319 +762,485 @@ fn deser_apdu_command(hex_str: &str) -> Result<APDUCommand<Vec<u8>>, Box<dyn err
 /// opening a socket so a remote server communicates directly with the Ledger. It appears to be
 /// talking to an HSM up there which would manage sensitive actions.
 /// Parameters are passed directly in the url. Don't forget to escape the necessary characters!
-pub fn query_via_websocket(
-    ledger_api: &TransportNativeHID,
+/// `on_progress` is called each time a command is sent to the device. Once `cancel` is triggered,
+/// the session stops before the next command. The HSM is given up once it stays silent for
+/// `hsm_timeout`. The socket is always closed with a close frame when the session ends. The
+/// device is reached from the blocking thread pool, so the socket is served meanwhile.
+pub async fn query_via_websocket(
+    ledger_api: &Arc<dyn Transport>,
     url: &str,
-) -> Result<(), Box<dyn error::Error>> {
-    let (mut socket, _) = tungstenite::connect(url)?;
+    hsm_timeout: Duration,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(HsmProgress),
+) -> Result<(), LedgerError> {
+    on_progress(HsmProgress {
+        phase: HsmPhase::Connecting,
+        index: 0,
+        total: 0,
+        bytes_sent: 0,
+    });
+    let (mut socket, _) = timeout(hsm_timeout, tokio_tungstenite::connect_async(url))
+        .await
+        .map_err(|_| LedgerError::Hsm("Timed out connecting.".to_string()))??;
+    let res = hsm_session(&mut socket, ledger_api, hsm_timeout, cancel, on_progress).await;
+    close_socket(&mut socket, hsm_timeout).await;
+    res
+}
+
+// https://github.com/LedgerHQ/ledger-live/blob/99879eb5bada1ecaea7a02d8886e16b44657af6d/libs/ledger-live-common/src/socket/index.ts#L95
+async fn hsm_session<S>(
+    socket: &mut S,
+    ledger_api: &Arc<dyn Transport>,
+    hsm_timeout: Duration,
+    cancel: &CancelToken,
+    on_progress: &mut dyn FnMut(HsmProgress),
+) -> Result<(), LedgerError>
+where
+    S: Stream<Item = Result<Message, WsError>> + Sink<Message, Error = WsError> + Unpin,
+{
+    let mut progress = HsmProgress {
+        phase: HsmPhase::Connecting,
+        index: 0,
+        total: 0,
+        bytes_sent: 0,
+    };
+    // The last error returned by the device, reported in place of the HSM's one if the session
+    // fails afterward.
+    let mut device_error: Option<LedgerError> = None;
 
-    // https://github.com/LedgerHQ/ledger-live/blob/99879eb5bada1ecaea7a02d8886e16b44657af6d/libs/ledger-live-common/src/socket/index.ts#L95
     loop {
-        let msg = socket.read()?;
-        match msg {
-            // It appears they only exchange JSON text messages.
-            tungstenite::Message::Text(text) => {
-                let msg: HsmMessage = serde_json::from_str(&text)?;
-
-                // The dance is usually:
-                // - first the HSM sends a few standalone commands;
-                // - then it sends a bunch in bulk;
-                // - finally it sends a success.
-                if msg.query == "exchange" {
-                    let command_hex = match msg.data {
-                        Some(HsmMessageData::Command(h)) => h,
-                        _ => return Err("A single command is expected in 'exchange' mode.".into()),
-                    };
-                    let command = deser_apdu_command(&command_hex)?;
-
-                    // NOTE: the HSM expects only the data, not the last two bytes of the raw
-                    // response (the status) in the "data" field below.
-                    let resp = ledger_api.exchange(&command)?;
-                    let response = if resp.retcode() == StatusCode::OK as u16 {
-                        "success"
-                    } else {
-                        eprintln!(
-                            "Error when installing app. Error code: {:#02x}. Resp: {:?}.",
-                            resp.retcode(),
-                            resp
-                        );
-                        "error"
-                    };
-                    let resp_data = hex::encode(resp.data());
-
-                    let ws_resp = serde_json::json!({
-                        "nonce": msg.nonce,
-                        "response": response,
-                        "data": resp_data,
-                    });
-                    socket.send(tungstenite::Message::Text(serde_json::to_string(&ws_resp)?))?;
-                } else if msg.query == "bulk" {
-                    // Ledger Live closes the socket immediately after receiving a bulk. It doesn't
-                    // appear to be necessary, on the contrary if we don't we get a clean "success"
-                    // response back. So we might as well do that.
-                    //socket.close(None).unwrap();
-
-                    let commands = match msg.data {
-                        Some(HsmMessageData::CommandList(l)) => l,
-                        _ => return Err("Expecting a list of commands in bulk mode.".into()),
-                    };
-                    for cmd_hex in commands {
-                        if cmd_hex.is_empty() {
-                            continue;
-                        }
-                        let command = deser_apdu_command(&cmd_hex)?;
-                        let _ = ledger_api.exchange(&command)?;
-                    }
-
-                    let ws_resp = serde_json::json!({
-                        "nonce": msg.nonce,
-                        "response": "success",
-                        "data": "",
-                    });
-                    socket.send(tungstenite::Message::Text(serde_json::to_string(&ws_resp)?))?;
-                } else if msg.query == "success" {
-                    return Ok(());
-                } else if msg.query == "error" {
-                    return Err(
-                        format!("Got an 'error' query on the ws. Full message: {}.", text).into(),
+        let text = read_hsm_message(socket, hsm_timeout, cancel).await?;
+        let msg: HsmMessage = serde_json::from_str(&text)?;
+
+        // The dance is usually:
+        // - first the HSM sends a few standalone commands;
+        // - then it sends a bunch in bulk;
+        // - finally it sends a success.
+        if msg.query == "exchange" {
+            let command_hex = match msg.data {
+                Some(HsmMessageData::Command(h)) => h,
+                _ => {
+                    return Err(LedgerError::Hsm(
+                        "A single command is expected in 'exchange' mode.".to_string(),
+                    ))
+                }
+            };
+            let command = deser_apdu_command(&command_hex)?;
+            progress.phase = HsmPhase::Exchange;
+            progress.bytes_sent += command_hex.len() / 2;
+            on_progress(progress);
+
+            // NOTE: the HSM expects only the data, not the last two bytes of the raw
+            // response (the status) in the "data" field below.
+            let resp = ledger_api.exchange_async(command).await?;
+            let response = match LedgerError::from_status(resp.retcode()) {
+                None => "success",
+                Some(e) => {
+                    log::warn!(
+                        "Device error during HSM exchange: {}",
+                        StatusCode::describe(resp.retcode())
                     );
-                } else if msg.query == "warning" {
-                    eprintln!("Got a 'warning' query on the ws. Full message: {}.", text);
-                } else {
-                    return Err(format!(
-                        "Got an unsupported query on the ws. Full message: {}.",
-                        text
-                    )
-                    .into());
+                    device_error = Some(e);
+                    "error"
+                }
+            };
+            let resp_data = hex::encode(resp.data());
+
+            send_hsm_message(
+                socket,
+                serde_json::json!({
+                    "nonce": msg.nonce,
+                    "response": response,
+                    "data": resp_data,
+                }),
+                hsm_timeout,
+            )
+            .await?;
+        } else if msg.query == "bulk" {
+            // Ledger Live closes the socket immediately after receiving a bulk. It doesn't
+            // appear to be necessary, on the contrary if we don't we get a clean "success"
+            // response back. So we might as well do that.
+            //socket.close(None).unwrap();
+
+            let commands = match msg.data {
+                Some(HsmMessageData::CommandList(l)) => l,
+                _ => {
+                    return Err(LedgerError::Hsm(
+                        "Expecting a list of commands in bulk mode.".to_string(),
+                    ))
+                }
+            };
+            progress.phase = HsmPhase::Bulk;
+            progress.index = 0;
+            progress.total = commands.len();
+            on_progress(progress);
+            for cmd_hex in commands {
+                if cancel.is_cancelled() {
+                    log::warn!(
+                        "Install cancelled after {}/{} commands, the app is incomplete.",
+                        progress.index,
+                        progress.total
+                    );
+                    return Err(LedgerError::Cancelled);
+                }
+                progress.index += 1;
+                if cmd_hex.is_empty() {
+                    continue;
+                }
+                let command = deser_apdu_command(&cmd_hex)?;
+                let resp = ledger_api.exchange_async(command).await?;
+                progress.bytes_sent += cmd_hex.len() / 2;
+                on_progress(progress);
+                if let Some(e) = LedgerError::from_status(resp.retcode()) {
+                    log::warn!(
+                        "Device error during HSM bulk: {}",
+                        StatusCode::describe(resp.retcode())
+                    );
+                    return Err(e);
                 }
             }
-            _ => {
-                return Err(format!(
-                    "Got an unsupported message type on the ws. Message: {:?}.",
-                    msg
-                )
-                .into())
-            }
+
+            send_hsm_message(
+                socket,
+                serde_json::json!({
+                    "nonce": msg.nonce,
+                    "response": "success",
+                    "data": "",
+                }),
+                hsm_timeout,
+            )
+            .await?;
+        } else if msg.query == "success" {
+            progress.phase = HsmPhase::Done;
+            on_progress(progress);
+            return Ok(());
+        } else if msg.query == "error" {
+            return Err(device_error.unwrap_or_else(|| {
+                LedgerError::Hsm(format!(
+                    "Got an 'error' query on the ws. Full message: {}.",
+                    text
+                ))
+            }));
+        } else if msg.query == "warning" {
+            log::warn!("Got a 'warning' query on the ws. Full message: {}.", text);
+        } else {
+            return Err(LedgerError::Hsm(format!(
+                "Got an unsupported query on the ws. Full message: {}.",
+                text
+            )));
         }
     }
 }
 
 /// Get a list of applications installed on this device.
-pub fn list_installed_apps(
-    ledger_api: &TransportNativeHID,
-) -> Result<Vec<InstalledApp>, Box<dyn error::Error>> {
+pub fn list_installed_apps(ledger_api: &dyn Transport) -> Result<Vec<InstalledApp>, LedgerError> {
     let mut answer = ledger_api.exchange(&LIST_APPS_COMMAND)?;
+    if let Some(e) = LedgerError::from_status(answer.retcode()) {
+        return Err(e);
+    }
     let mut data = answer.data();
 
-    // See https://github.com/LedgerHQ/ledger-live/blob/99879eb5bada1ecaea7a02d8886e16b44657af6d/libs/ledger-live-common/src/hw/listApps.ts#L9
     let mut installed_apps = Vec::new();
     while !data.is_empty() {
-        let mut i = 0;
-        assert_eq!(data[i], 0x01);
-        i += 1;
-
-        while i < data.len() {
-            if data.len() < i + 1 + 2 + 2 + 32 + 32 + 1 {
-                return Err("Not enough data".into());
-            }
-
-            let len = data[i] as usize;
-            i += 1;
-            let blocks = u16::from_be_bytes(data[i..i + 2].try_into()?);
-            i += 2;
-            let flags = u16::from_be_bytes(data[i..i + 2].try_into()?);
-            i += 2;
-            let hash_code_data = data[i..i + 32].to_vec();
-            i += 32;
-            let hash = data[i..i + 32].to_vec();
-            i += 32;
-            let name_len = data[i] as usize;
-            i += 1;
-
-            if data.len() < i + name_len {
-                return Err("Not enough data".into());
-            }
-            if len != name_len + 70 {
-                return Err("Invalid listApps length data.".into());
-            }
-            let name = str::from_utf8(&data[i..i + name_len])?.to_string();
-            i += name_len;
-
-            installed_apps.push(InstalledApp {
-                name,
-                hash,
-                hash_code_data,
-                blocks,
-                flags,
-            });
-        }
+        installed_apps.extend(parse_list_apps_page(data)?);
 
         answer = ledger_api.exchange(&CONTINUE_LIST_APPS_COMMAND)?;
+        if let Some(e) = LedgerError::from_status(answer.retcode()) {
+            return Err(e);
+        }
         data = answer.data();
     }
 
     Ok(installed_apps)
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct DeviceVersion {
-    pub id: i64,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-pub struct FirmwareInfo {
-    pub perso: String,
-}
-
-impl FirmwareInfo {
-    #[allow(unused)]
-    pub fn from_device(device_info: &DeviceInfo) -> Self {
-        let dev_ver_resp = minreq::Request::new(
-            minreq::Method::Post,
-            &format!("{}/get_device_version", BASE_API_V1_URL),
-        )
-        .with_param("livecommonversion", LIVE_COMMON_VERSION)
-        .with_json(&serde_json::json!({
-        "provider": PROVIDER,
-        "target_id": device_info.target_id,
-        }))
-        .unwrap()
-        .send()
-        .unwrap();
-        let device_version = dev_ver_resp.json::<DeviceVersion>().unwrap();
-
-        let firm_resp = minreq::Request::new(
-            minreq::Method::Post,
-            &format!("{}/get_firmware_version", BASE_API_V1_URL),
-        )
-        .with_param("livecommonversion", LIVE_COMMON_VERSION)
-        .with_json(&serde_json::json!({
-        "provider": PROVIDER,
-        "device_version": device_version.id,
-        "version_name": &device_info.version,
-        }))
-        .unwrap()
-        .send()
-        .unwrap();
-        firm_resp.json::<FirmwareInfo>().unwrap()
-    }
-}
-
-// DON'T DELETE ME JUST YET.
-// This appears to be the "old" (api v1) way of querying information about the Bitcoin app for a
-// device. It does give access to more data, so i'm keeping it around for now just in case.
-//
-// See
-// https://github.com/LedgerHQ/ledger-live/blob/99879eb5bada1ecaea7a02d8886e16b44657af6d/libs/ledger-live-common/src/manager/index.ts#L103-L104.
-//
-// See above for firmware info.
-//
-//let compatible_apps = minreq::Request::new(
-//minreq::Method::Post,
-//"https://manager.api.live.ledger.com/api/get_apps",
-//)
-//.with_param("livecommonversion", "34.0.0")
-//.with_json(&serde_json::json!({
-//"provider": PROVIDER,
-//"current_se_firmware_final_version": firmware_id,
-//"device_version": device_id,
-//}))
-//.unwrap()
-//.with_param("firmware_version_name", device_info.version)
-//.send()
-//.unwrap();
-//let bitcoin_apps: Vec<_> = compatible_apps
-//.json::<serde_json::Value>()
-//.unwrap()
-//.get("application_versions")
-//.unwrap()
-//.as_array()
-//.unwrap()
-//.into_iter()
-//.filter(|o| {
-//o.as_object()
-//.unwrap()
-//.get("name")
-//.unwrap()
-//.as_str()
-//.unwrap()
-//.to_lowercase()
-//== "bitcoin test"
-////.contains("bitcoin")
-//})
-////.inspect(|o| println!("{}", serde_json::to_string_pretty(&o).unwrap()))
-//.cloned()
-//.collect();
-//let bitcoin_app = &bitcoin_apps[0];
-//println!("{}", bitcoin_app);
-
-#[derive(Debug, Clone, Deserialize)]
-pub struct BitcoinAppV2 {
-    #[serde(rename = "versionName")]
-    pub version_name: String,
-    pub perso: String,
-    #[serde(rename = "deleteKey")]
-    pub delete_key: String,
-    pub firmware: String,
-    #[serde(rename = "firmwareKey")]
-    pub firmware_key: String,
-    pub hash: String,
-}
-
-/// Get the Bitcoin app information for this device. Set `is_testnet` to `true` to get the Test app
-/// instead.
-// This uses the v2 API. See for reference:
-// - https://github.com/LedgerHQ/ledger-live/blob/5a0a1aa5dc183116839851b79bceb6704f1de4b9/libs/ledger-live-common/src/apps/listApps/v2.ts
-// - https://github.com/LedgerHQ/ledger-live/blob/5a0a1aa5dc183116839851b79bceb6704f1de4b9/libs/device-core/src/managerApi/repositories/HttpManagerApiRepository.ts#L211
-// There is also another way which seems to be the API v1 way of getting the app info. See
-// above the commented out code.
-pub fn bitcoin_app(
-    device_info: &DeviceInfo,
-    is_testnet: bool,
-) -> Result<Option<BitcoinAppV2>, Box<dyn error::Error>> {
-    let lowercase_app_name = if is_testnet {
-        "bitcoin test"
-    } else {
-        "bitcoin"
-    };
-    log::debug!("call ledger API");
-    let resp_apps = minreq::Request::new(
-        minreq::Method::Get,
-        format!("{}/apps/by-target", BASE_API_V2_URL),
-    )
-    .with_param("livecommonversion", "34.0.0")
-    .with_param("provider", PROVIDER.to_string()) // TODO: allow to configure the provider
-    .with_param("target_id", device_info.target_id.to_string())
-    .with_param("firmware_version_name", device_info.version.clone())
-    .send()?;
-    log::debug!("get response from ledger API");
-    resp_apps
-        .json::<Vec<BitcoinAppV2>>()
-        // FIXME: is versionName guaranteed to be the name? What's "version" for?
-        .map(|apps| {
-            apps.into_iter()
-                .find(|o| o.version_name.to_lowercase() == lowercase_app_name)
-        })
-        .map_err(|e| e.into())
+/// Get the name and version of the app running on the device. Unlike `GET_VERSION`, this command
+/// is also answered by the apps.
+pub fn get_app_and_version(ledger_api: &dyn Transport) -> Result<RunningApp, LedgerError> {
+    let answer = ledger_api.exchange(&GET_APP_AND_VERSION_COMMAND)?;
+    if let Some(e) = LedgerError::from_status(answer.retcode()) {
+        return Err(e);
+    }
+    Ok(parse_app_and_version(answer.data())?)
 }
 
 /// Open the given application on the device.
 #[allow(unused)]
-pub fn open_bitcoin_app(
-    ledger_api: &TransportNativeHID,
-    is_testnet: bool,
-) -> Result<(), Box<dyn error::Error>> {
+pub fn open_app(ledger_api: &dyn Transport, app: &AppId) -> Result<(), LedgerError> {
     let mut command = OPEN_APP_COMMAND_TEMPLATE;
-    command.data = if is_testnet {
-        b"Bitcoin Test"
-    } else {
-        b"Bitcoin"
-    };
+    command.data = app.0.as_bytes();
 
     let resp = ledger_api.exchange(&command)?;
-    if resp.retcode() != StatusCode::OK as u16 {
-        return Err(format!("Error opening app. Ledger response: {:#x?}.", resp).into());
+    match LedgerError::from_status(resp.retcode()) {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::{RecordedExchange, ReplayTransport, Transcript};
+
+    // GET_VERSION answers, without their status word.
+    const NANO_S_1_6_1: &str = "3110000405312e362e3104a600000004312e3132";
+    const NANO_S_2_1_0: &str = "3110000405322e312e3004e600000005312e313200";
+    const NANO_X_2_2_3: &str = "3300000405322e322e3304e600000005322e33300004312e313601000100";
+    const NANO_SP_1_1_1: &str = "3310000405312e312e3104e600000005342e30330004342e303301000101";
+    const STAX_1_4_0: &str = "3320000405312e342e3004e600000005352e32340004352e323401000100";
+    const NANO_X_OSU: &str = "3300000409322e322e332d6f7375040000000005322e33300004312e313601000100";
+    const NANO_X_BOOTLOADER: &str = "0100000104312e31360005322e322e330433000004";
+    const NANO_S_BOOTLOADER: &str = "0100000104302e3131000431100004";
+
+    // LIST_APPS pages: Bitcoin and Bitcoin Test, then Ethereum.
+    const FIRST_PAGE: &str =
+        "014d00900a50f1fe327a278c699e1453db1db423ee73bd2be167c3684d00f0747f1da8b4\
+        56abb4056df6691f8dc72e56302ddad345d65fead3ead9299609a826e2344eb63aa407426974636f696e52009\
+        80a506e9635e6b07e1c1f7d4405cc15f00f1a2c61d79e114a3c36b0e929cbee8990a04941dd1d6fe968b3fcb6\
+        5c08d76667b036d9a737919f4b34f30b07832ccc6e510c426974636f696e2054657374";
+    const CONTINUATION_PAGE: &str = "014e03000a50fd9461d239035bd27d9f4cbc3161b31227036fe03230206\
+        503f220f5ff58677ba13bebeb57e1ea699bd4d2d9ac7e58399644e884b8a8783d96f6d146083f243008457468\
+        657265756d";
+
+    fn version(answer: &str) -> DeviceInfo {
+        parse_get_version(&hex::decode(answer).unwrap()).unwrap()
+    }
+
+    /// A xorshift generator, so the random inputs are the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+            let len = self.next() as usize % (max_len + 1);
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+    }
+
+    #[test]
+    fn get_version_nano_s() {
+        let info = version(NANO_S_2_1_0);
+        assert_eq!(info.target_id, 0x31100004);
+        assert_eq!(info.model(), Model::NanoS);
+        assert_eq!(info.version, "2.1.0");
+        assert!(!info.is_bootloader && !info.is_osu);
+        assert_eq!(info.mcu_version.as_deref(), Some("1.12"));
+        assert_eq!(info.se_version.as_deref(), Some("2.1.0"));
+        assert!(info.status.onboarded && info.status.pin_validated);
+        assert!(!info.status.manager_allowed && !info.status.recovery_mode);
+        assert_eq!(info.language_id, None);
+    }
+
+    #[test]
+    fn get_version_without_trailing_fields() {
+        let info = version(NANO_S_1_6_1);
+        assert_eq!(info.model(), Model::NanoS);
+        assert_eq!(info.version, "1.6.1");
+        assert_eq!(info.mcu_version.as_deref(), Some("1.12"));
+        assert_eq!(info.language_id, None);
+    }
+
+    #[test]
+    fn get_version_with_trailing_fields() {
+        let info = version(NANO_X_2_2_3);
+        assert_eq!(info.model(), Model::NanoX);
+        assert_eq!(info.version, "2.2.3");
+        assert_eq!(info.mcu_version.as_deref(), Some("2.30"));
+        assert_eq!(info.language_id, Some(0));
+
+        let info = version(NANO_SP_1_1_1);
+        assert_eq!(info.model(), Model::NanoSP);
+        assert_eq!(info.version, "1.1.1");
+        assert_eq!(info.mcu_version.as_deref(), Some("4.03"));
+        assert_eq!(info.language_id, Some(1));
+
+        let info = version(STAX_1_4_0);
+        assert_eq!(info.model(), Model::Stax);
+        assert_eq!(info.version, "1.4.0");
+        assert_eq!(info.mcu_version.as_deref(), Some("5.24"));
+    }
+
+    #[test]
+    fn get_version_osu() {
+        let info = version(NANO_X_OSU);
+        assert!(info.is_osu);
+        assert!(!info.is_bootloader);
+        assert_eq!(info.version, "2.2.3");
+        assert!(!info.status.onboarded);
+    }
+
+    #[test]
+    fn get_version_bootloader() {
+        let info = version(NANO_X_BOOTLOADER);
+        assert!(info.is_bootloader);
+        assert_eq!(info.version, "1.16");
+        assert_eq!(info.se_version.as_deref(), Some("2.2.3"));
+        assert_eq!(info.se_target_id, 0x33000004);
+
+        let info = version(NANO_S_BOOTLOADER);
+        assert!(info.is_bootloader);
+        assert_eq!(info.se_version, None);
+        assert_eq!(info.se_target_id, 0x31100004);
+    }
+
+    #[test]
+    fn list_apps_pages() {
+        let apps = parse_list_apps_page(&hex::decode(FIRST_PAGE).unwrap()).unwrap();
+        let names: Vec<_> = apps.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["Bitcoin", "Bitcoin Test"]);
+        assert_eq!(apps[0].blocks, 0x90);
+        assert_eq!(apps[0].flags, 0x0a50);
+        assert_eq!(
+            hex::encode(&apps[0].hash),
+            "b4056df6691f8dc72e56302ddad345d65fead3ead9299609a826e2344eb63aa4"
+        );
+
+        let apps = parse_list_apps_page(&hex::decode(CONTINUATION_PAGE).unwrap()).unwrap();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].name, "Ethereum");
+        assert_eq!(apps[0].blocks, 0x300);
+
+        assert!(parse_list_apps_page(&[0x01]).unwrap().is_empty());
+        assert_eq!(
+            parse_list_apps_page(&[0x02]).unwrap_err(),
+            ParseError::UnexpectedFormat(0x02)
+        );
+    }
+
+    #[test]
+    fn list_installed_apps_until_empty_page() {
+        let exchange = |command: &APDUCommand<&[u8]>, response: &str| RecordedExchange {
+            command: hex::encode(command.serialize()),
+            response: response.to_string(),
+            status_word: StatusCode::OK as u16,
+            elapsed_ms: 0,
+            error: None,
+        };
+        let transport = ReplayTransport::new(Transcript {
+            exchanges: vec![
+                exchange(&LIST_APPS_COMMAND, FIRST_PAGE),
+                exchange(&CONTINUE_LIST_APPS_COMMAND, CONTINUATION_PAGE),
+                exchange(&CONTINUE_LIST_APPS_COMMAND, ""),
+            ],
+        });
+        let names: Vec<_> = list_installed_apps(&transport)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.name)
+            .collect();
+        assert_eq!(names, ["Bitcoin", "Bitcoin Test", "Ethereum"]);
+    }
+
+    #[tokio::test]
+    async fn cancel_token() {
+        let cancel = CancelToken::default();
+        assert_eq!(cancel.run(async { 1 }).await, Ok(1));
+
+        let waiting = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { cancel.run(std::future::pending::<()>()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        cancel.cancel();
+        assert_eq!(waiting.await.unwrap(), Err(LedgerError::Cancelled));
+
+        // Once cancelled, nothing runs anymore.
+        assert_eq!(cancel.run(async { 1 }).await, Err(LedgerError::Cancelled));
+        cancel.cancelled().await;
+    }
+
+    #[test]
+    fn app_and_version() {
+        let app = parse_app_and_version(&hex::decode("0105424f4c4f5305312e362e31").unwrap());
+        assert_eq!(app.unwrap().name, DASHBOARD_NAME);
+        assert!(parse_app_and_version(&[0x01, 0x05, b'B']).is_err());
+    }
+
+    #[test]
+    fn truncated_get_version_fails() {
+        for answer in [
+            NANO_S_1_6_1,
+            NANO_S_2_1_0,
+            NANO_X_2_2_3,
+            NANO_SP_1_1_1,
+            STAX_1_4_0,
+            NANO_X_OSU,
+            NANO_X_BOOTLOADER,
+            NANO_S_BOOTLOADER,
+        ] {
+            let data = hex::decode(answer).unwrap();
+            // Offsets where each length-prefixed field ends.
+            let mut ends = Vec::new();
+            let mut reader = Reader::new(&data);
+            reader.u32().unwrap();
+            while !reader.is_empty() {
+                reader.lv().unwrap();
+                ends.push(reader.pos);
+            }
+            // Firmwares may stop after the MCU version, the bootloader answer has no optional
+            // field.
+            let required = if version(answer).is_bootloader {
+                data.len()
+            } else {
+                ends[2]
+            };
+            for len in 0..data.len() {
+                let res = parse_get_version(&data[..len]);
+                let valid = len >= required && ends.contains(&len);
+                assert_eq!(res.is_ok(), valid, "{} cut at {}", answer, len);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_list_apps_page_fails() {
+        let data = hex::decode(FIRST_PAGE).unwrap();
+        let second_entry = 1 + data[1] as usize;
+        for len in 0..data.len() {
+            let res = parse_list_apps_page(&data[..len]);
+            match len {
+                1 => assert!(res.unwrap().is_empty()),
+                len if len == second_entry => assert_eq!(res.unwrap().len(), 1),
+                _ => assert!(res.is_err(), "cut at {}", len),
+            }
+        }
+    }
+
+    #[test]
+    fn random_input_never_panics() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for _ in 0..20_000 {
+            let data = rng.bytes(160);
+            let _ = parse_get_version(&data);
+            let _ = parse_app_and_version(&data);
+            // Mostly valid pages, so the parsing goes past the format byte.
+            let mut page = data.clone();
+            page.insert(0, 0x01);
+            let _ = parse_list_apps_page(&page);
+            let _ = parse_list_apps_page(&data);
+        }
+    }
+
+    #[test]
+    fn corrupted_answers_never_panic() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for answer in [NANO_X_2_2_3, NANO_X_BOOTLOADER, FIRST_PAGE] {
+            let data = hex::decode(answer).unwrap();
+            for _ in 0..5_000 {
+                let mut corrupted = data.clone();
+                for _ in 0..1 + rng.next() % 4 {
+                    let i = rng.next() as usize % corrupted.len();
+                    corrupted[i] = rng.next() as u8;
+                }
+                let _ = parse_get_version(&corrupted);
+                let _ = parse_list_apps_page(&corrupted);
+            }
+        }
+    }
 }
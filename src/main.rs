@@ -1,21 +1,48 @@
 mod client;
 mod color;
+mod config;
+mod error;
 mod gui;
+mod hotplug;
 mod ledger;
 mod ledger_lib;
 mod ledger_manager;
 mod theme;
+mod transcript;
+mod transport;
 mod logger;
+mod manager_api;
+mod model;
+mod mock_device;
+mod mock_hsm;
+mod speculos;
 
-use crate::{client::ClientFn, gui::{Flags, LedgerInstaller}, ledger::LedgerClient};
+use crate::{client::ClientFn, config::Config, gui::{Flags, LedgerInstaller}, ledger::LedgerClient, mock_hsm::MockHsm};
 
 use iced::{window::icon, Application, Settings, Size};
 
 
 #[tokio::main]
 async fn main() {
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
     logger::set_logger(true);
-    
+
+    // Keep the mock HSM alive for the whole session.
+    let _mock_hsm = match config.mock_hsm.take().map(MockHsm::start).transpose() {
+        Ok(hsm) => hsm.inspect(|hsm| config.manager_api.socket_url = hsm.url()),
+        Err(e) => {
+            eprintln!("Fail to start the mock HSM: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     let (ledger_sender, gui_ledger_receiver) = async_channel::unbounded();
     let (gui_ledger_sender, ledger_receiver) = async_channel::unbounded();
 
@@ -24,16 +51,22 @@ async fn main() {
         ledger_receiver: gui_ledger_receiver,
     };
 
-    let ledger = LedgerClient::new(ledger_sender, ledger_receiver, gui_ledger_sender);
+    let ledger = LedgerClient::new(ledger_sender, ledger_receiver, gui_ledger_sender)
+        .with_transport(config.transport)
+        .with_recorder(config.recorder)
+        .with_manager_api(config.manager_api);
     ledger.start();
 
     const ICON: &[u8] = include_bytes!("sardine.png");
     let icon = icon::from_file_data(ICON, None).unwrap();
 
     let mut settings = Settings::with_flags(flags);
-    settings.window.size = Size::new(500.0, 200.0);
-    settings.window.resizable = false;
+    settings.window.size = Size::new(600.0, 450.0);
+    settings.window.min_size = Some(Size::new(500.0, 250.0));
+    settings.window.resizable = true;
     settings.window.icon = Some(icon);
+    // Closing while an app is being written must be confirmed, see `Message::CloseRequested`.
+    settings.window.exit_on_close_request = false;
 
     LedgerInstaller::run(settings).expect("Fail to launch application!")
 }
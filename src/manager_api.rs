@@ -0,0 +1,725 @@
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+
+use std::{error, fmt, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+#[cfg(test)]
+use std::sync::Mutex;
+
+use crate::ledger_lib::DeviceInfo;
+
+pub const LIVE_COMMON_VERSION: &str = "34.0.0";
+pub const PROVIDER: u32 = 1;
+pub const BASE_API_V1_URL: &str = "https://manager.api.live.ledger.com/api";
+pub const BASE_API_V2_URL: &str = "https://manager.api.live.ledger.com/api/v2";
+pub const BASE_SOCKET_URL: &str = "wss://scriptrunner.api.live.ledger.com/update";
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Delay after which the HSM is considered unresponsive.
+pub const DEFAULT_HSM_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub enum ManagerApiError {
+    /// The request could not be sent or the response could not be received.
+    Http(String),
+    /// The server answered with a non success status code.
+    Status(i32, String),
+    /// The response body is not what we expected.
+    Json(String),
+}
+
+impl fmt::Display for ManagerApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManagerApiError::Http(e) => write!(f, "HTTP error: {}", e),
+            ManagerApiError::Status(code, body) => {
+                write!(f, "Manager API answered with status {}: {}", code, body)
+            }
+            ManagerApiError::Json(e) => write!(f, "Invalid manager API response: {}", e),
+        }
+    }
+}
+
+impl error::Error for ManagerApiError {}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HttpMethod {
+    Get,
+    Post,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+    pub url: String,
+    pub params: Vec<(String, String)>,
+    pub json: Option<serde_json::Value>,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: i32,
+    pub body: Vec<u8>,
+}
+
+pub type HttpFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<HttpResponse, ManagerApiError>> + Send + 'a>>;
+
+/// The HTTP client used to reach the manager API.
+pub trait HttpBackend: fmt::Debug + Send + Sync {
+    fn send(&self, request: HttpRequest) -> HttpFuture<'_>;
+}
+
+/// Async HTTP backend based on `reqwest`.
+#[derive(Debug, Default)]
+pub struct ReqwestBackend {
+    client: reqwest::Client,
+}
+
+impl HttpBackend for ReqwestBackend {
+    fn send(&self, request: HttpRequest) -> HttpFuture<'_> {
+        Box::pin(async move {
+            let method = match request.method {
+                HttpMethod::Get => reqwest::Method::GET,
+                HttpMethod::Post => reqwest::Method::POST,
+            };
+            let mut req = self
+                .client
+                .request(method, &request.url)
+                .query(&request.params)
+                .timeout(request.timeout);
+            if let Some(json) = &request.json {
+                req = req.json(json);
+            }
+            let resp = req
+                .send()
+                .await
+                .map_err(|e| ManagerApiError::Http(e.to_string()))?;
+            let status = resp.status().as_u16() as i32;
+            let body = resp
+                .bytes()
+                .await
+                .map_err(|e| ManagerApiError::Http(e.to_string()))?;
+            Ok(HttpResponse {
+                status,
+                body: body.to_vec(),
+            })
+        })
+    }
+}
+
+/// Answers the requests with canned responses, picked by the end of their url, and keeps them
+/// for inspection.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct StubBackend {
+    responses: Mutex<Vec<(String, HttpResponse)>>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+#[cfg(test)]
+impl StubBackend {
+    /// Answer the requests to urls ending with `path`.
+    pub fn respond(&self, path: &str, status: i32, body: &str) {
+        let response = HttpResponse {
+            status,
+            body: body.as_bytes().to_vec(),
+        };
+        self.responses
+            .lock()
+            .unwrap()
+            .push((path.to_string(), response));
+    }
+
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl HttpBackend for StubBackend {
+    fn send(&self, request: HttpRequest) -> HttpFuture<'_> {
+        let response = self
+            .responses
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(path, _)| request.url.ends_with(path.as_str()))
+            .map(|(_, response)| response.clone())
+            .ok_or_else(|| ManagerApiError::Http(format!("No stub for {}", request.url)));
+        self.requests.lock().unwrap().push(request);
+        Box::pin(async move { response })
+    }
+}
+
+/// Client of Ledger's manager API, also holding the HSM endpoint used for installs.
+#[derive(Debug, Clone)]
+pub struct ManagerApi {
+    pub api_v1_url: String,
+    pub api_v2_url: String,
+    pub socket_url: String,
+    pub live_common_version: String,
+    pub provider: u32,
+    pub timeout: Duration,
+    /// How long the HSM may stay silent before the session is given up.
+    pub hsm_timeout: Duration,
+    http: Arc<dyn HttpBackend>,
+}
+
+impl Default for ManagerApi {
+    fn default() -> Self {
+        Self {
+            api_v1_url: BASE_API_V1_URL.to_string(),
+            api_v2_url: BASE_API_V2_URL.to_string(),
+            socket_url: BASE_SOCKET_URL.to_string(),
+            live_common_version: LIVE_COMMON_VERSION.to_string(),
+            provider: PROVIDER,
+            timeout: DEFAULT_TIMEOUT,
+            hsm_timeout: DEFAULT_HSM_TIMEOUT,
+            http: Arc::new(ReqwestBackend::default()),
+        }
+    }
+}
+
+/// Partial [`ManagerApi`] settings, as found in the settings file. Missing fields keep their
+/// previous value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManagerApiSettings {
+    pub api_v1_url: Option<String>,
+    pub api_v2_url: Option<String>,
+    pub socket_url: Option<String>,
+    pub live_common_version: Option<String>,
+    pub provider: Option<u32>,
+    pub timeout_secs: Option<u64>,
+    pub hsm_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceVersion {
+    pub id: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct FirmwareInfo {
+    pub id: i64,
+    pub name: String,
+    pub perso: String,
+}
+
+/// The firmware update available for a device, if any.
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct LatestFirmware {
+    pub id: i64,
+    pub name: String,
+    pub perso: String,
+    pub firmware: String,
+    pub firmware_key: String,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct LatestFirmwareResponse {
+    result: String,
+    se_firmware_osu_version: Option<LatestFirmware>,
+}
+
+/// Identify an app of the catalog by its name, as listed on the device.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AppId(pub String);
+
+impl AppId {
+    pub fn bitcoin() -> Self {
+        AppId("Bitcoin".to_string())
+    }
+
+    pub fn bitcoin_test() -> Self {
+        AppId("Bitcoin Test".to_string())
+    }
+
+    /// The apps always offered, whether they are installed or not.
+    pub fn featured() -> Vec<Self> {
+        vec![Self::bitcoin(), Self::bitcoin_test()]
+    }
+}
+
+impl fmt::Display for AppId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An app of the catalog, as available for a given device and firmware.
+// See https://github.com/LedgerHQ/ledger-live/blob/5a0a1aa5dc183116839851b79bceb6704f1de4b9/libs/device-core/src/managerApi/entities/AppEntities.ts
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+pub struct CatalogApp {
+    pub version_name: String,
+    /// Version of the app, missing from some entries, see [`CatalogApp::version`].
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub version_display_name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Name of the icon, to be fetched from the CDN.
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub category: Option<u32>,
+    /// The app this one depends on, like Ethereum for the EVM chains.
+    #[serde(default)]
+    pub parent_name: Option<String>,
+    /// Size of the app binary.
+    #[serde(default)]
+    pub bytes: Option<u32>,
+    pub perso: String,
+    /// Script removing the app, used in place of `firmware`.
+    pub delete: String,
+    pub delete_key: String,
+    pub firmware: String,
+    pub firmware_key: String,
+    pub hash: String,
+}
+
+/// A version of an app, for one device and firmware.
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct ApplicationVersion {
+    pub name: String,
+    pub version: String,
+    pub firmware: Option<String>,
+    /// Hex encoded hash of the app, the one reported by the device in `LIST_APPS`.
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+/// An app of the manager catalog, with all its known versions.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Application {
+    pub name: String,
+    #[serde(default)]
+    pub application_versions: Vec<ApplicationVersion>,
+}
+
+impl Application {
+    /// Find the version of this app having the given hash.
+    pub fn version_by_hash(&self, hash: &[u8]) -> Option<&ApplicationVersion> {
+        let hash = hex::encode(hash);
+        self.application_versions.iter().find(|v| {
+            v.hash
+                .as_ref()
+                .map(|h| h.eq_ignore_ascii_case(&hash))
+                .unwrap_or(false)
+        })
+    }
+}
+
+impl CatalogApp {
+    pub fn id(&self) -> AppId {
+        AppId(self.version_name.clone())
+    }
+
+    /// Whether this is the app identified by `id`.
+    pub fn is(&self, id: &AppId) -> bool {
+        // FIXME: is versionName guaranteed to be the name? What's "version" for?
+        self.version_name.to_lowercase() == id.0.to_lowercase()
+    }
+
+    /// Whether `hash`, as listed by the device, is the one of this build.
+    pub fn has_hash(&self, hash: &[u8]) -> bool {
+        self.hash.eq_ignore_ascii_case(&hex::encode(hash))
+    }
+
+    /// The version of the app. When the API doesn't give it, it's parsed from the firmware path,
+    /// None if the path isn't `<device>/<firmware>/<app>/app_<version>`.
+    // example for nano s: nanos/2.1.0/bitcoin_testnet/app_2.2.1
+    // example for nano s+: nanos+/1.1.0/bitcoin_testnet/app_2.2.0-beta
+    pub fn version(&self) -> Option<&str> {
+        if let Some(version) = self.version.as_deref().filter(|v| !v.is_empty()) {
+            return Some(version);
+        }
+        match self.firmware.split('/').collect::<Vec<_>>()[..] {
+            [_, _, _, app] => app.strip_prefix("app_").filter(|v| !v.is_empty()),
+            _ => None,
+        }
+    }
+
+    /// The apps to install before this one.
+    pub fn dependencies(&self) -> Vec<AppId> {
+        self.parent_name
+            .iter()
+            .filter(|parent| !parent.is_empty())
+            .map(|parent| AppId(parent.clone()))
+            .collect()
+    }
+
+    /// Storage used by the app, in blocks of `block_size` bytes.
+    pub fn blocks(&self, block_size: u32) -> Option<u32> {
+        let bytes = self.bytes?;
+        (block_size > 0).then(|| bytes.div_ceil(block_size))
+    }
+}
+
+impl ManagerApi {
+    pub fn apply(&mut self, settings: ManagerApiSettings) {
+        if let Some(url) = settings.api_v1_url {
+            self.api_v1_url = url;
+        }
+        if let Some(url) = settings.api_v2_url {
+            self.api_v2_url = url;
+        }
+        if let Some(url) = settings.socket_url {
+            self.socket_url = url;
+        }
+        if let Some(version) = settings.live_common_version {
+            self.live_common_version = version;
+        }
+        if let Some(provider) = settings.provider {
+            self.provider = provider;
+        }
+        if let Some(secs) = settings.timeout_secs {
+            self.timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = settings.hsm_timeout_secs {
+            self.hsm_timeout = Duration::from_secs(secs);
+        }
+    }
+
+    /// Use another HTTP client than the default `reqwest` one.
+    #[allow(unused)]
+    pub fn with_http_backend(mut self, http: Arc<dyn HttpBackend>) -> Self {
+        self.http = http;
+        self
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: HttpMethod,
+        url: String,
+        mut params: Vec<(String, String)>,
+        json: Option<serde_json::Value>,
+    ) -> Result<T, ManagerApiError> {
+        params.push((
+            "livecommonversion".to_string(),
+            self.live_common_version.clone(),
+        ));
+        let resp = self
+            .http
+            .send(HttpRequest {
+                method,
+                url,
+                params,
+                json,
+                timeout: self.timeout,
+            })
+            .await?;
+        if !(200..300).contains(&resp.status) {
+            return Err(ManagerApiError::Status(
+                resp.status,
+                String::from_utf8_lossy(&resp.body).to_string(),
+            ));
+        }
+        serde_json::from_slice(&resp.body).map_err(|e| ManagerApiError::Json(e.to_string()))
+    }
+
+    pub async fn get_device_version(
+        &self,
+        target_id: u32,
+    ) -> Result<DeviceVersion, ManagerApiError> {
+        self.request(
+            HttpMethod::Post,
+            format!("{}/get_device_version", self.api_v1_url),
+            Vec::new(),
+            Some(serde_json::json!({
+                "provider": self.provider,
+                "target_id": target_id,
+            })),
+        )
+        .await
+    }
+
+    pub async fn get_firmware_version(
+        &self,
+        device_version: &DeviceVersion,
+        version_name: &str,
+    ) -> Result<FirmwareInfo, ManagerApiError> {
+        self.request(
+            HttpMethod::Post,
+            format!("{}/get_firmware_version", self.api_v1_url),
+            Vec::new(),
+            Some(serde_json::json!({
+                "provider": self.provider,
+                "device_version": device_version.id,
+                "version_name": version_name,
+            })),
+        )
+        .await
+    }
+
+    /// Get the firmware currently running on this device.
+    #[allow(unused)]
+    pub async fn firmware_info(
+        &self,
+        device_info: &DeviceInfo,
+    ) -> Result<FirmwareInfo, ManagerApiError> {
+        let device_version = self.get_device_version(device_info.target_id).await?;
+        self.get_firmware_version(&device_version, &device_info.version)
+            .await
+    }
+
+    /// Get the firmware update available for this device, if any.
+    #[allow(unused)]
+    pub async fn get_latest_firmware(
+        &self,
+        device_version: &DeviceVersion,
+        current_firmware: &FirmwareInfo,
+    ) -> Result<Option<LatestFirmware>, ManagerApiError> {
+        let resp: LatestFirmwareResponse = self
+            .request(
+                HttpMethod::Post,
+                format!("{}/get_latest_firmware", self.api_v1_url),
+                Vec::new(),
+                Some(serde_json::json!({
+                    "provider": self.provider,
+                    "current_se_firmware_final_version": current_firmware.id,
+                    "device_version": device_version.id,
+                })),
+            )
+            .await?;
+        Ok(if resp.result == "null" {
+            None
+        } else {
+            resp.se_firmware_osu_version
+        })
+    }
+
+    /// Get the whole catalog of the manager: every app with all its versions, for all the devices
+    /// and firmwares.
+    // This uses the v1 API, the v2 one only knows the latest version of each app. See
+    // https://github.com/LedgerHQ/ledger-live/blob/99879eb5bada1ecaea7a02d8886e16b44657af6d/libs/ledger-live-common/src/manager/api.ts
+    pub async fn applications(&self) -> Result<Vec<Application>, ManagerApiError> {
+        self.request(
+            HttpMethod::Get,
+            format!("{}/applications", self.api_v1_url),
+            Vec::new(),
+            None,
+        )
+        .await
+    }
+
+    /// Get all the apps available for this device.
+    // This uses the v2 API. See for reference:
+    // - https://github.com/LedgerHQ/ledger-live/blob/5a0a1aa5dc183116839851b79bceb6704f1de4b9/libs/ledger-live-common/src/apps/listApps/v2.ts
+    // - https://github.com/LedgerHQ/ledger-live/blob/5a0a1aa5dc183116839851b79bceb6704f1de4b9/libs/device-core/src/managerApi/repositories/HttpManagerApiRepository.ts#L211
+    pub async fn apps_by_target(
+        &self,
+        device_info: &DeviceInfo,
+    ) -> Result<Vec<CatalogApp>, ManagerApiError> {
+        log::debug!("call ledger API");
+        let apps = self
+            .request(
+                HttpMethod::Get,
+                format!("{}/apps/by-target", self.api_v2_url),
+                vec![
+                    ("provider".to_string(), self.provider.to_string()),
+                    ("target_id".to_string(), device_info.target_id.to_string()),
+                    (
+                        "firmware_version_name".to_string(),
+                        device_info.version.clone(),
+                    ),
+                ],
+                None,
+            )
+            .await;
+        log::debug!("get response from ledger API");
+        apps
+    }
+
+    /// Get the information of the app identified by `id` for this device.
+    pub async fn app(
+        &self,
+        device_info: &DeviceInfo,
+        id: &AppId,
+    ) -> Result<Option<CatalogApp>, ManagerApiError> {
+        Ok(self
+            .apps_by_target(device_info)
+            .await?
+            .into_iter()
+            .find(|o| o.is(id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger_lib::parse_get_version;
+
+    const NANO_X_2_2_3: &str = "3300000405322e322e3304e600000005322e33300004312e313601000100";
+
+    // Trimmed answers of the manager API.
+    const APPS_BY_TARGET: &str = r#"[
+        {
+            "versionName": "Bitcoin",
+            "version": "2.2.2",
+            "versionDisplayName": "Bitcoin",
+            "description": null,
+            "icon": "bitcoin",
+            "category": 1,
+            "parentName": null,
+            "bytes": 81920,
+            "perso": "perso_11",
+            "delete": "nanox/2.2.3/bitcoin/app_2.2.2_del",
+            "deleteKey": "nanox/2.2.3/bitcoin/app_2.2.2_del_key",
+            "firmware": "nanox/2.2.3/bitcoin/app_2.2.2",
+            "firmwareKey": "nanox/2.2.3/bitcoin/app_2.2.2_key",
+            "hash": "b4056df6691f8dc72e56302ddad345d65fead3ead9299609a826e2344eb63aa4"
+        },
+        {
+            "versionName": "Bitcoin Test",
+            "parentName": "Bitcoin",
+            "perso": "perso_11",
+            "delete": "nanox/2.2.3/bitcoin_testnet/app_2.2.2_del",
+            "deleteKey": "nanox/2.2.3/bitcoin_testnet/app_2.2.2_del_key",
+            "firmware": "nanox/2.2.3/bitcoin_testnet/app_2.2.2",
+            "firmwareKey": "nanox/2.2.3/bitcoin_testnet/app_2.2.2_key",
+            "hash": "4941dd1d6fe968b3fcb65c08d76667b036d9a737919f4b34f30b07832ccc6e51"
+        }
+    ]"#;
+    const LATEST_FIRMWARE: &str = r#"{
+        "result": "success",
+        "se_firmware_osu_version": {
+            "id": 410,
+            "name": "2.4.0-osu",
+            "perso": "perso_11",
+            "firmware": "nanox/2.4.0/fw_2.2.3/upgrade_osu_2.4.0",
+            "firmware_key": "nanox/2.4.0/fw_2.2.3/upgrade_osu_2.4.0_key",
+            "hash": "0c2b3ba6bb82a6e3bc3ba1c9d0a0c3f3d7a5b4b6f0f3b0a7c4a2b1e3d9e8f7a6"
+        }
+    }"#;
+
+    fn api() -> (ManagerApi, Arc<StubBackend>) {
+        let stub = Arc::new(StubBackend::default());
+        let api = ManagerApi::default().with_http_backend(stub.clone());
+        (api, stub)
+    }
+
+    fn param<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+        request
+            .params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[tokio::test]
+    async fn apps_by_target() {
+        let (api, stub) = api();
+        stub.respond("/apps/by-target", 200, APPS_BY_TARGET);
+        let info = parse_get_version(&hex::decode(NANO_X_2_2_3).unwrap()).unwrap();
+
+        let apps = api.apps_by_target(&info).await.unwrap();
+        assert_eq!(apps.len(), 2);
+        assert!(apps[0].is(&AppId::bitcoin()));
+        assert_eq!(apps[0].version.as_deref(), Some("2.2.2"));
+        assert_eq!(apps[0].version(), Some("2.2.2"));
+        // Parsed from the firmware path.
+        assert_eq!(apps[1].version, None);
+        assert_eq!(apps[1].version(), Some("2.2.2"));
+        assert_eq!(apps[0].blocks(4096), Some(20));
+        assert_eq!(apps[1].dependencies(), vec![AppId::bitcoin()]);
+
+        let request = &stub.requests()[0];
+        assert_eq!(request.url, format!("{}/apps/by-target", BASE_API_V2_URL));
+        assert_eq!(param(request, "target_id"), Some("855638020"));
+        assert_eq!(param(request, "firmware_version_name"), Some("2.2.3"));
+        assert_eq!(
+            param(request, "livecommonversion"),
+            Some(LIVE_COMMON_VERSION)
+        );
+
+        let app = api.app(&info, &AppId::bitcoin_test()).await.unwrap();
+        assert_eq!(app.map(|a| a.id()), Some(AppId::bitcoin_test()));
+    }
+
+    #[test]
+    fn version_from_unexpected_path() {
+        let mut apps: Vec<CatalogApp> = serde_json::from_str(APPS_BY_TARGET).unwrap();
+        let mut app = apps.remove(1);
+        for firmware in [
+            "bitcoin_testnet/app_2.2.2",
+            "nanox/2.2.3/bitcoin_testnet/2.2.2/app",
+        ] {
+            app.firmware = firmware.to_string();
+            assert_eq!(app.version(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn firmware() {
+        let (api, stub) = api();
+        stub.respond("/get_device_version", 200, r#"{"id": 17}"#);
+        stub.respond(
+            "/get_firmware_version",
+            200,
+            r#"{"id": 361, "name": "2.2.3", "perso": "perso_11"}"#,
+        );
+        stub.respond("/get_latest_firmware", 200, LATEST_FIRMWARE);
+        let info = parse_get_version(&hex::decode(NANO_X_2_2_3).unwrap()).unwrap();
+
+        let firmware = api.firmware_info(&info).await.unwrap();
+        assert_eq!(firmware.id, 361);
+        let requests = stub.requests();
+        let body = requests[1].json.as_ref().unwrap();
+        assert_eq!(body["device_version"], 17);
+        assert_eq!(body["version_name"], "2.2.3");
+
+        let device_version = DeviceVersion { id: 17 };
+        let latest = api
+            .get_latest_firmware(&device_version, &firmware)
+            .await
+            .unwrap();
+        assert_eq!(latest.map(|f| f.name), Some("2.4.0-osu".to_string()));
+        let body = stub.requests()[2].json.clone().unwrap();
+        assert_eq!(body["current_se_firmware_final_version"], 361);
+    }
+
+    #[tokio::test]
+    async fn no_firmware_update() {
+        let (api, stub) = api();
+        stub.respond("/get_latest_firmware", 200, r#"{"result": "null"}"#);
+        let firmware = FirmwareInfo {
+            id: 361,
+            name: "2.2.3".to_string(),
+            perso: "perso_11".to_string(),
+        };
+        let latest = api
+            .get_latest_firmware(&DeviceVersion { id: 17 }, &firmware)
+            .await
+            .unwrap();
+        assert!(latest.is_none());
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let (api, stub) = api();
+        stub.respond("/applications", 502, "Bad Gateway");
+        stub.respond("/get_device_version", 200, "<html>");
+        assert!(matches!(
+            api.applications().await,
+            Err(ManagerApiError::Status(502, body)) if body == "Bad Gateway"
+        ));
+        assert!(matches!(
+            api.get_device_version(0x33000004).await,
+            Err(ManagerApiError::Json(_))
+        ));
+        let info = parse_get_version(&hex::decode(NANO_X_2_2_3).unwrap()).unwrap();
+        assert!(matches!(
+            api.apps_by_target(&info).await,
+            Err(ManagerApiError::Http(_))
+        ));
+    }
+}
@@ -0,0 +1,510 @@
+use ledger_apdu::{APDUAnswer, APDUCommand};
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::{
+    model::Model,
+    transport::{Transport, TransportError},
+};
+
+const SW_OK: u16 = 0x9000;
+const SW_LOCKED_DEVICE: u16 = 0x5515;
+const SW_USER_REFUSED_ON_DEVICE: u16 = 0x5501;
+const SW_NOT_ENOUGH_SPACE: u16 = 0x5102;
+const SW_CLA_NOT_SUPPORTED: u16 = 0x6e00;
+const SW_INS_NOT_SUPPORTED: u16 = 0x6d00;
+const SW_INCORRECT_DATA: u16 = 0x6a80;
+const SW_APP_NOT_FOUND: u16 = 0x6807;
+const SW_FILE_ALREADY_EXISTS: u16 = 0x6a89;
+const SW_FILE_NOT_FOUND: u16 = 0x9404;
+
+// Instructions answered by the dashboard and the apps (cla 0xb0).
+const CLA_BOLOS: u8 = 0xb0;
+const INS_GET_APP_AND_VERSION: u8 = 0x01;
+
+// Dashboard instructions (cla 0xe0).
+const INS_GET_VERSION: u8 = 0x01;
+const INS_SECURE: u8 = 0x00;
+const INS_INIT_AUTHENTICATION: u8 = 0x50;
+const INS_VALIDATE_CERTIFICATE: u8 = 0x51;
+const INS_GET_CERTIFICATE: u8 = 0x52;
+const INS_MUTUAL_AUTHENTICATE: u8 = 0x53;
+const INS_OPEN_APP: u8 = 0xd8;
+const INS_LIST_APPS: u8 = 0xde;
+const INS_CONTINUE_LIST_APPS: u8 = 0xdf;
+
+// Loader sub-commands, first byte of the data of an `INS_SECURE` command.
+const SECUREINS_LOAD: u8 = 0x06;
+const SECUREINS_COMMIT: u8 = 0x09;
+const SECUREINS_CREATE_APP: u8 = 0x0b;
+const SECUREINS_DELETE_APP: u8 = 0x0c;
+
+// Flags returned by GET_VERSION.
+const FLAG_ONBOARDED: u8 = 0x04;
+const FLAG_PIN_VALIDATED: u8 = 0x80;
+
+/// Low bits of the target id, the same for all the models.
+const TARGET_ID_LOW_BITS: u32 = 0x0004;
+
+/// The firmware a mock device of this model runs, unless told otherwise.
+fn default_firmware(model: Model) -> &'static str {
+    match model {
+        Model::NanoS => "2.1.0",
+        Model::NanoSP => "1.1.1",
+        Model::NanoX => "2.2.3",
+        Model::Stax => "1.4.0",
+        Model::Flex | Model::Unknown => "1.0.0",
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockApp {
+    pub name: String,
+    pub hash: Vec<u8>,
+    pub hash_code_data: Vec<u8>,
+    pub blocks: u16,
+    pub flags: u16,
+}
+
+#[cfg(test)]
+impl MockApp {
+    pub fn new(name: &str, hash: [u8; 32], blocks: u16) -> Self {
+        Self {
+            name: name.to_string(),
+            hash: hash.to_vec(),
+            hash_code_data: hash.to_vec(),
+            blocks,
+            flags: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    model: Model,
+    version: String,
+    plugged: bool,
+    locked: bool,
+    onboarded: bool,
+    bootloader: bool,
+    refuse: bool,
+    apps: Vec<MockApp>,
+    open_app: Option<String>,
+    list_cursor: Option<usize>,
+    manager_allowed: bool,
+    pending_app: Option<MockApp>,
+    forced_status: VecDeque<u16>,
+}
+
+/// A fake device answering the dashboard APDUs this project sends, keeping an in-memory list of
+/// installed apps.
+///
+/// Since the commands pushed by Ledger's HSM are encrypted, the mock only understands a plain
+/// flavor of the loader protocol, sent in the data of `e0 00 00 00` commands:
+/// - `0x0b | blocks (2) | hash (32) | name length (1) | name`: create an app;
+/// - `0x06 | ...`: load a chunk of the app (ignored);
+/// - `0x09`: commit the app created last;
+/// - `0x0c | name length (1) | name`: delete an app.
+///
+/// The secure channel handshake (`e0 50` to `e0 53`) is accepted, the first command asking the
+/// (simulated) user to allow the manager.
+#[derive(Debug)]
+pub struct MockDevice {
+    state: Mutex<MockState>,
+}
+
+impl MockDevice {
+    pub fn new(model: Model) -> Self {
+        Self {
+            state: Mutex::new(MockState {
+                model,
+                version: default_firmware(model).to_string(),
+                plugged: true,
+                locked: false,
+                onboarded: true,
+                bootloader: false,
+                refuse: false,
+                apps: Vec::new(),
+                open_app: None,
+                list_cursor: None,
+                manager_allowed: false,
+                pending_app: None,
+                forced_status: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Whether the device is connected, it can't be reached otherwise.
+    pub fn is_plugged(&self) -> bool {
+        self.with_state(|s| s.plugged)
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut MockState) -> R) -> R {
+        let mut state = match self.state.lock() {
+            Ok(s) => s,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        f(&mut state)
+    }
+}
+
+/// Setters simulating what the user does with the device.
+#[cfg(test)]
+impl MockDevice {
+    pub fn with_app(self, app: MockApp) -> Self {
+        self.with_state(|s| s.apps.push(app));
+        self
+    }
+
+    /// Unplugging also locks the device.
+    pub fn set_plugged(&self, plugged: bool) {
+        self.with_state(|s| s.plugged = plugged);
+        if !plugged {
+            self.set_locked(true);
+        }
+    }
+
+    pub fn set_version(&self, version: &str) {
+        self.with_state(|s| s.version = version.to_string());
+    }
+
+    /// Lock or unlock the device. Locking also closes any open app.
+    pub fn set_locked(&self, locked: bool) {
+        self.with_state(|s| {
+            s.locked = locked;
+            if locked {
+                s.open_app = None;
+                s.manager_allowed = false;
+            }
+        });
+    }
+
+    pub fn set_onboarded(&self, onboarded: bool) {
+        self.with_state(|s| s.onboarded = onboarded);
+    }
+
+    pub fn set_bootloader(&self, bootloader: bool) {
+        self.with_state(|s| s.bootloader = bootloader);
+    }
+
+    /// If set, the simulated user refuses every on-device confirmation.
+    pub fn set_refuse(&self, refuse: bool) {
+        self.with_state(|s| s.refuse = refuse);
+    }
+
+    /// Close the open app, going back to the dashboard.
+    pub fn quit_app(&self) {
+        self.with_state(|s| s.open_app = None);
+    }
+
+    /// Answer the next command with the given status word, whatever the command is.
+    pub fn push_status(&self, status: u16) {
+        self.with_state(|s| s.forced_status.push_back(status));
+    }
+
+    pub fn apps(&self) -> Vec<MockApp> {
+        self.with_state(|s| s.apps.clone())
+    }
+
+    pub fn open_app(&self) -> Option<String> {
+        self.with_state(|s| s.open_app.clone())
+    }
+}
+
+impl MockState {
+    fn handle(&mut self, command: &APDUCommand<&[u8]>) -> (Vec<u8>, u16) {
+        if let Some(status) = self.forced_status.pop_front() {
+            return (Vec::new(), status);
+        }
+        if command.cla == CLA_BOLOS && command.ins == INS_GET_APP_AND_VERSION {
+            if self.locked {
+                return (Vec::new(), SW_LOCKED_DEVICE);
+            }
+            return (self.app_and_version(), SW_OK);
+        }
+        if command.cla != 0xe0 || self.open_app.is_some() {
+            return (Vec::new(), SW_CLA_NOT_SUPPORTED);
+        }
+        if self.locked {
+            return (Vec::new(), SW_LOCKED_DEVICE);
+        }
+
+        match command.ins {
+            INS_GET_VERSION => (self.get_version(), SW_OK),
+            INS_LIST_APPS => {
+                if !self.allow_manager() {
+                    return (Vec::new(), SW_USER_REFUSED_ON_DEVICE);
+                }
+                self.list_cursor = Some(0);
+                self.list_apps_page()
+            }
+            INS_CONTINUE_LIST_APPS => self.list_apps_page(),
+            INS_OPEN_APP => {
+                if self.refuse {
+                    return (Vec::new(), SW_USER_REFUSED_ON_DEVICE);
+                }
+                let name = String::from_utf8_lossy(command.data).to_string();
+                if self.apps.iter().any(|a| a.name == name) {
+                    self.open_app = Some(name);
+                    (Vec::new(), SW_OK)
+                } else {
+                    (Vec::new(), SW_APP_NOT_FOUND)
+                }
+            }
+            INS_INIT_AUTHENTICATION => {
+                if !self.allow_manager() {
+                    return (Vec::new(), SW_USER_REFUSED_ON_DEVICE);
+                }
+                (vec![0; 8], SW_OK)
+            }
+            INS_VALIDATE_CERTIFICATE | INS_MUTUAL_AUTHENTICATE => (Vec::new(), SW_OK),
+            INS_GET_CERTIFICATE => (vec![0; 32], SW_OK),
+            INS_SECURE => (Vec::new(), self.loader(command.data)),
+            _ => (Vec::new(), SW_INS_NOT_SUPPORTED),
+        }
+    }
+
+    /// Simulate the "Allow Ledger manager" prompt, only asked once per session.
+    fn allow_manager(&mut self) -> bool {
+        if !self.manager_allowed && !self.refuse {
+            self.manager_allowed = true;
+        }
+        self.manager_allowed
+    }
+
+    fn app_and_version(&self) -> Vec<u8> {
+        let (name, version) = match &self.open_app {
+            Some(name) => (name.as_str(), "1.0.0"),
+            None => ("BOLOS", self.version.as_str()),
+        };
+        let mut data = vec![0x01, name.len() as u8];
+        data.extend_from_slice(name.as_bytes());
+        data.push(version.len() as u8);
+        data.extend_from_slice(version.as_bytes());
+        // No flags.
+        data.push(0);
+        data
+    }
+
+    fn get_version(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut flags = 0u8;
+        if self.onboarded {
+            flags |= FLAG_ONBOARDED;
+        }
+        if !self.locked {
+            flags |= FLAG_PIN_VALIDATED;
+        }
+
+        if self.bootloader {
+            let version = b"1.16";
+            data.extend_from_slice(&0x01000001u32.to_be_bytes());
+            data.push(version.len() as u8);
+            data.extend_from_slice(version);
+            data.push(0);
+            data.push(4);
+            data.extend_from_slice(&self.target_id().to_be_bytes());
+        } else {
+            let mcu_version = b"2.30\0";
+            data.extend_from_slice(&self.target_id().to_be_bytes());
+            data.push(self.version.len() as u8);
+            data.extend_from_slice(self.version.as_bytes());
+            data.push(4);
+            data.extend_from_slice(&[flags, 0, 0, 0]);
+            data.push(mcu_version.len() as u8);
+            data.extend_from_slice(mcu_version);
+        }
+        data
+    }
+
+    /// One app per page, an empty page marking the end of the list.
+    fn list_apps_page(&mut self) -> (Vec<u8>, u16) {
+        let cursor = match self.list_cursor {
+            Some(c) => c,
+            None => return (Vec::new(), SW_INCORRECT_DATA),
+        };
+        let app = match self.apps.get(cursor) {
+            Some(app) => app,
+            None => {
+                self.list_cursor = None;
+                return (Vec::new(), SW_OK);
+            }
+        };
+        self.list_cursor = Some(cursor + 1);
+
+        let mut data = vec![0x01];
+        data.push((app.name.len() + 70) as u8);
+        data.extend_from_slice(&app.blocks.to_be_bytes());
+        data.extend_from_slice(&app.flags.to_be_bytes());
+        data.extend_from_slice(&app.hash_code_data);
+        data.extend_from_slice(&app.hash);
+        data.push(app.name.len() as u8);
+        data.extend_from_slice(app.name.as_bytes());
+        (data, SW_OK)
+    }
+
+    fn target_id(&self) -> u32 {
+        self.model
+            .spec()
+            .map_or(0, |spec| spec.target_id_mask | TARGET_ID_LOW_BITS)
+    }
+
+    /// Number of storage blocks available for applications, whose size depends on the firmware.
+    fn capacity_blocks(&self) -> u32 {
+        match (
+            self.model.memory_size(),
+            self.model.block_size(&self.version),
+        ) {
+            (Some(memory_size), Some(block_size)) => memory_size / block_size,
+            _ => 0,
+        }
+    }
+
+    fn used_blocks(&self) -> u32 {
+        self.apps.iter().map(|a| a.blocks as u32).sum()
+    }
+
+    fn loader(&mut self, data: &[u8]) -> u16 {
+        if !self.manager_allowed {
+            return SW_USER_REFUSED_ON_DEVICE;
+        }
+        match data.first() {
+            Some(&SECUREINS_CREATE_APP) => {
+                if data.len() < 1 + 2 + 32 + 1 {
+                    return SW_INCORRECT_DATA;
+                }
+                let blocks = u16::from_be_bytes([data[1], data[2]]);
+                let hash = data[3..35].to_vec();
+                let name_len = data[35] as usize;
+                let name = match data.get(36..36 + name_len) {
+                    Some(n) => String::from_utf8_lossy(n).to_string(),
+                    None => return SW_INCORRECT_DATA,
+                };
+                if self.apps.iter().any(|a| a.name == name) {
+                    return SW_FILE_ALREADY_EXISTS;
+                }
+                if self.used_blocks() + blocks as u32 > self.capacity_blocks() {
+                    return SW_NOT_ENOUGH_SPACE;
+                }
+                self.pending_app = Some(MockApp {
+                    name,
+                    hash_code_data: hash.clone(),
+                    hash,
+                    blocks,
+                    flags: 0,
+                });
+                SW_OK
+            }
+            Some(&SECUREINS_LOAD) => {
+                if self.pending_app.is_some() {
+                    SW_OK
+                } else {
+                    SW_INCORRECT_DATA
+                }
+            }
+            Some(&SECUREINS_COMMIT) => match self.pending_app.take() {
+                Some(app) => {
+                    self.apps.push(app);
+                    SW_OK
+                }
+                None => SW_INCORRECT_DATA,
+            },
+            Some(&SECUREINS_DELETE_APP) => {
+                let name_len = match data.get(1) {
+                    Some(l) => *l as usize,
+                    None => return SW_INCORRECT_DATA,
+                };
+                let name = match data.get(2..2 + name_len) {
+                    Some(n) => String::from_utf8_lossy(n).to_string(),
+                    None => return SW_INCORRECT_DATA,
+                };
+                let len = self.apps.len();
+                self.apps.retain(|a| a.name != name);
+                if self.apps.len() == len {
+                    SW_FILE_NOT_FOUND
+                } else {
+                    SW_OK
+                }
+            }
+            _ => SW_INS_NOT_SUPPORTED,
+        }
+    }
+}
+
+impl Transport for MockDevice {
+    fn exchange_apdu(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError> {
+        if !self.is_plugged() {
+            return Err(TransportError::Other(
+                "The device was unplugged.".to_string(),
+            ));
+        }
+        let (mut answer, status) = self.with_state(|s| s.handle(command));
+        answer.extend_from_slice(&status.to_be_bytes());
+        APDUAnswer::from_answer(answer).map_err(|_| TransportError::InvalidAnswer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Exchange;
+
+    /// Try to create an app of `blocks` blocks, returning the status word.
+    fn create_app(device: &MockDevice, blocks: u16) -> u16 {
+        let mut data = vec![SECUREINS_CREATE_APP];
+        data.extend_from_slice(&blocks.to_be_bytes());
+        data.extend_from_slice(&[0; 32]);
+        data.extend_from_slice(&[3, b'B', b'i', b'g']);
+        let command = APDUCommand {
+            cla: 0xe0,
+            ins: INS_SECURE,
+            p1: 0,
+            p2: 0,
+            data,
+        };
+        device.exchange(&command).unwrap().retcode()
+    }
+
+    fn allow_manager(device: &MockDevice) {
+        let command = APDUCommand {
+            cla: 0xe0,
+            ins: INS_INIT_AUTHENTICATION,
+            p1: 0,
+            p2: 0,
+            data: vec![0; 8],
+        };
+        assert_eq!(device.exchange(&command).unwrap().retcode(), SW_OK);
+    }
+
+    #[test]
+    fn capacity_depends_on_firmware() {
+        // 320 KiB, in blocks of 4 KiB before 2.0 then of 2 KiB.
+        let device = MockDevice::new(Model::NanoS);
+        device.set_version("1.6.1");
+        allow_manager(&device);
+        assert_eq!(create_app(&device, 81), SW_NOT_ENOUGH_SPACE);
+        assert_eq!(create_app(&device, 80), SW_OK);
+
+        let device = MockDevice::new(Model::NanoS);
+        allow_manager(&device);
+        assert_eq!(create_app(&device, 161), SW_NOT_ENOUGH_SPACE);
+        assert_eq!(create_app(&device, 160), SW_OK);
+    }
+
+    #[test]
+    fn target_id_matches_model() {
+        for model in [
+            Model::NanoS,
+            Model::NanoSP,
+            Model::NanoX,
+            Model::Stax,
+            Model::Flex,
+        ] {
+            let info = crate::ledger_lib::DeviceInfo::new(&MockDevice::new(model)).unwrap();
+            assert_eq!(info.model(), model);
+        }
+    }
+}
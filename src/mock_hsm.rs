@@ -0,0 +1,363 @@
+use serde_derive::Deserialize;
+
+use std::{
+    fs,
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tungstenite::Message;
+
+/// A step of a scripted HSM session.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HsmStep {
+    /// Send a single APDU and wait for the client answer.
+    Exchange { apdu: String },
+    /// Send a list of APDUs and wait for the client answer.
+    Bulk { apdus: Vec<String> },
+    /// End the session successfully.
+    Success,
+    /// End the session with an error.
+    Error { data: String },
+    /// Send a warning, the session goes on.
+    Warning { data: String },
+    /// Send a text frame as is, e.g. malformed JSON.
+    Raw { text: String },
+    /// Send a binary frame, which the client does not expect.
+    Binary { data: Vec<u8> },
+    /// Wait before the next step.
+    Delay { ms: u64 },
+    /// Keep the connection open without sending anything, to exercise timeouts.
+    Stall,
+    /// Close the connection.
+    Close,
+}
+
+/// The steps played on each connection to the mock HSM.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HsmScript {
+    pub steps: Vec<HsmStep>,
+}
+
+impl HsmScript {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Fail to read HSM script {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid HSM script {}: {}", path.display(), e))
+    }
+}
+
+/// Sessions driving a [`crate::mock_device::MockDevice`].
+#[cfg(test)]
+impl HsmScript {
+    /// A session installing an app on a [`crate::mock_device::MockDevice`], using its plain
+    /// loader commands.
+    pub fn install(name: &str, hash: [u8; 32], blocks: u16) -> Self {
+        let mut create = vec![0x0b];
+        create.extend_from_slice(&blocks.to_be_bytes());
+        create.extend_from_slice(&hash);
+        create.push(name.len() as u8);
+        create.extend_from_slice(name.as_bytes());
+
+        Self {
+            steps: vec![
+                HsmStep::Exchange {
+                    apdu: "e050000008".to_string() + &"00".repeat(8),
+                },
+                HsmStep::Exchange {
+                    apdu: "e052000000".to_string(),
+                },
+                HsmStep::Exchange {
+                    apdu: "e053000000".to_string(),
+                },
+                HsmStep::Bulk {
+                    apdus: vec![
+                        secure_apdu(&create),
+                        secure_apdu(&[0x06, 0x00, 0x00]),
+                        secure_apdu(&[0x09]),
+                    ],
+                },
+                HsmStep::Success,
+            ],
+        }
+    }
+
+    /// A session deleting an app from a [`crate::mock_device::MockDevice`].
+    pub fn uninstall(name: &str) -> Self {
+        let mut delete = vec![0x0c, name.len() as u8];
+        delete.extend_from_slice(name.as_bytes());
+
+        Self {
+            steps: vec![
+                HsmStep::Exchange {
+                    apdu: "e050000008".to_string() + &"00".repeat(8),
+                },
+                HsmStep::Bulk {
+                    apdus: vec![secure_apdu(&delete)],
+                },
+                HsmStep::Success,
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+fn secure_apdu(data: &[u8]) -> String {
+    let mut apdu = vec![0xe0, 0x00, 0x00, 0x00, data.len() as u8];
+    apdu.extend_from_slice(data);
+    hex::encode(apdu)
+}
+
+/// A local stand-in for Ledger's scriptrunner websocket, playing the same script to every client
+/// connecting to it.
+pub struct MockHsm {
+    addr: SocketAddr,
+    #[cfg(test)]
+    answers: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl MockHsm {
+    pub fn start(script: HsmScript) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let answers = Arc::new(Mutex::new(Vec::new()));
+
+        let session_answers = answers.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let script = script.clone();
+                        let answers = session_answers.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = run_session(stream, &script, &answers) {
+                                log::debug!("Mock HSM session ended: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => log::debug!("Mock HSM fail to accept connection: {}", e),
+                }
+            }
+        });
+        log::info!("Mock HSM listening on {}", addr);
+
+        Ok(Self {
+            addr,
+            #[cfg(test)]
+            answers,
+        })
+    }
+
+    /// Base url to use in place of `ManagerApi::socket_url`.
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// All the answers received from clients so far.
+    #[cfg(test)]
+    pub fn answers(&self) -> Vec<serde_json::Value> {
+        match self.answers.lock() {
+            Ok(a) => a.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+fn run_session(
+    stream: TcpStream,
+    script: &HsmScript,
+    answers: &Mutex<Vec<serde_json::Value>>,
+) -> Result<(), String> {
+    let mut socket = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+    let mut nonce = 0;
+
+    for step in &script.steps {
+        let query = match step {
+            HsmStep::Exchange { apdu } => Some(("exchange", serde_json::json!(apdu))),
+            HsmStep::Bulk { apdus } => Some(("bulk", serde_json::json!(apdus))),
+            HsmStep::Success => Some(("success", serde_json::Value::Null)),
+            HsmStep::Error { data } => Some(("error", serde_json::json!(data))),
+            HsmStep::Warning { data } => Some(("warning", serde_json::json!(data))),
+            HsmStep::Raw { text } => {
+                socket
+                    .send(Message::Text(text.clone()))
+                    .map_err(|e| e.to_string())?;
+                None
+            }
+            HsmStep::Binary { data } => {
+                socket
+                    .send(Message::Binary(data.clone()))
+                    .map_err(|e| e.to_string())?;
+                None
+            }
+            HsmStep::Delay { ms } => {
+                thread::sleep(Duration::from_millis(*ms));
+                None
+            }
+            HsmStep::Stall => loop {
+                // Only returns once the client goes away.
+                socket.read().map_err(|e| e.to_string())?;
+            },
+            HsmStep::Close => {
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                return Ok(());
+            }
+        };
+
+        if let Some((query, data)) = query {
+            nonce += 1;
+            let msg = serde_json::json!({
+                "query": query,
+                "nonce": nonce,
+                "data": data,
+            });
+            socket
+                .send(Message::Text(msg.to_string()))
+                .map_err(|e| e.to_string())?;
+
+            if matches!(step, HsmStep::Exchange { .. } | HsmStep::Bulk { .. }) {
+                let answer = match socket.read().map_err(|e| e.to_string())? {
+                    Message::Text(text) => {
+                        serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text))
+                    }
+                    msg => return Err(format!("Unexpected message from client: {:?}", msg)),
+                };
+                log::debug!("Mock HSM got {}", answer);
+                if let Ok(mut answers) = answers.lock() {
+                    answers.push(answer);
+                }
+            }
+        }
+    }
+
+    let _ = socket.close(None);
+    let _ = socket.flush();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::LedgerError,
+        ledger_lib::{query_via_websocket, CancelToken, HsmPhase},
+        mock_device::MockDevice,
+        model::Model,
+        transport::Transport,
+    };
+    use std::time::Instant;
+
+    const HASH: [u8; 32] = [0xb4; 32];
+
+    /// Play `script` against `device`, returning how the session ended and the phases reported.
+    async fn run(
+        script: HsmScript,
+        device: &Arc<MockDevice>,
+        hsm_timeout: Duration,
+    ) -> (Result<(), LedgerError>, Vec<HsmPhase>, MockHsm) {
+        let hsm = MockHsm::start(script).unwrap();
+        let transport: Arc<dyn Transport> = device.clone();
+        let mut phases = Vec::new();
+        let res = query_via_websocket(
+            &transport,
+            &hsm.url(),
+            hsm_timeout,
+            &CancelToken::default(),
+            &mut |progress| phases.push(progress.phase),
+        )
+        .await;
+        (res, phases, hsm)
+    }
+
+    fn script(steps: Vec<HsmStep>) -> HsmScript {
+        HsmScript { steps }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn install() {
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        let script = HsmScript::install("Bitcoin", HASH, 20);
+        let (res, phases, hsm) = run(script, &device, Duration::from_secs(5)).await;
+        assert_eq!(res, Ok(()));
+        assert_eq!(phases.first(), Some(&HsmPhase::Connecting));
+        assert!(phases.contains(&HsmPhase::Bulk));
+        assert_eq!(phases.last(), Some(&HsmPhase::Done));
+
+        let answers = hsm.answers();
+        assert_eq!(answers.len(), 4);
+        for (i, answer) in answers.iter().enumerate() {
+            assert_eq!(answer["nonce"], i + 1);
+            assert_eq!(answer["response"], "success");
+        }
+        let apps = device.apps();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].name, "Bitcoin");
+        assert_eq!(apps[0].hash, HASH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hsm_error() {
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        let steps = vec![HsmStep::Error {
+            data: "Invalid perso".to_string(),
+        }];
+        let (res, _, _) = run(script(steps), &device, Duration::from_secs(5)).await;
+        assert!(matches!(res, Err(LedgerError::Hsm(e)) if e.contains("Invalid perso")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn device_errors() {
+        // The app to delete is not there.
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        let script = HsmScript::uninstall("Ethereum");
+        let (res, _, _) = run(script, &device, Duration::from_secs(5)).await;
+        assert_eq!(res, Err(LedgerError::Device(0x9404)));
+
+        // The answer to a refused standalone command is forwarded to the HSM, the session only
+        // fails once the device refuses the bulk. The manager was already allowed on the first
+        // device, so ask a fresh one.
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        device.set_refuse(true);
+        let script = HsmScript::uninstall("Ethereum");
+        let (res, _, hsm) = run(script, &device, Duration::from_secs(5)).await;
+        assert_eq!(res, Err(LedgerError::UserRefused(0x5501)));
+        assert_eq!(hsm.answers()[0]["response"], "error");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unexpected_messages() {
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        for step in [
+            HsmStep::Raw {
+                text: "{not json".to_string(),
+            },
+            HsmStep::Binary { data: vec![0x01] },
+            HsmStep::Close,
+        ] {
+            let (res, _, _) = run(script(vec![step]), &device, Duration::from_secs(5)).await;
+            assert!(res.is_err());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn silent_hsm() {
+        let device = Arc::new(MockDevice::new(Model::NanoX));
+        let start = Instant::now();
+        let (res, _, _) = run(
+            script(vec![HsmStep::Stall]),
+            &device,
+            Duration::from_secs(1),
+        )
+        .await;
+        assert_eq!(
+            res,
+            Err(LedgerError::Hsm("The HSM stopped answering.".to_string()))
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt::{Display, Formatter};
+
+// Adapted from https://github.com/LedgerHQ/ledger-live/blob/dcbda65e65ead4014e767778da6022b78d8eddad/libs/ledgerjs/packages/devices/src/index.ts#L3-L156
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screen {
+    /// Monochrome screen driven by the two buttons.
+    Monochrome { width: u16, height: u16 },
+    /// E-ink touchscreen.
+    EInkTouch { width: u16, height: u16 },
+}
+
+/// What we know about a device model.
+#[derive(Debug, Clone, Copy)]
+pub struct ModelSpec {
+    pub model: Model,
+    /// Value of `target_id & 0xffff0000` for this model.
+    pub target_id_mask: u32,
+    /// USB product id used by the old firmwares.
+    pub legacy_usb_product_id: u16,
+    /// High byte of the USB product id used by the newer firmwares.
+    pub usb_product_id_mm: u8,
+    /// Storage available for the firmware and the apps, in bytes.
+    pub memory_size: u32,
+    pub screen: Screen,
+}
+
+const MODELS: [ModelSpec; 5] = [
+    ModelSpec {
+        model: Model::NanoS,
+        target_id_mask: 0x31100000,
+        legacy_usb_product_id: 0x0001,
+        usb_product_id_mm: 0x10,
+        memory_size: 320 * 1024,
+        screen: Screen::Monochrome {
+            width: 128,
+            height: 32,
+        },
+    },
+    ModelSpec {
+        model: Model::NanoX,
+        target_id_mask: 0x33000000,
+        legacy_usb_product_id: 0x0004,
+        usb_product_id_mm: 0x40,
+        memory_size: 2 * 1024 * 1024,
+        screen: Screen::Monochrome {
+            width: 128,
+            height: 64,
+        },
+    },
+    ModelSpec {
+        model: Model::NanoSP,
+        target_id_mask: 0x33100000,
+        legacy_usb_product_id: 0x0005,
+        usb_product_id_mm: 0x50,
+        memory_size: 1533 * 1024,
+        screen: Screen::Monochrome {
+            width: 128,
+            height: 64,
+        },
+    },
+    ModelSpec {
+        model: Model::Stax,
+        target_id_mask: 0x33200000,
+        legacy_usb_product_id: 0x0006,
+        usb_product_id_mm: 0x60,
+        memory_size: 1533 * 1024,
+        screen: Screen::EInkTouch {
+            width: 400,
+            height: 672,
+        },
+    },
+    ModelSpec {
+        model: Model::Flex,
+        target_id_mask: 0x33300000,
+        legacy_usb_product_id: 0x0007,
+        usb_product_id_mm: 0x70,
+        memory_size: 1533 * 1024,
+        screen: Screen::EInkTouch {
+            width: 480,
+            height: 600,
+        },
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    NanoS,
+    NanoSP,
+    NanoX,
+    Stax,
+    Flex,
+    Unknown,
+}
+
+#[allow(unused)]
+impl Model {
+    /// Identify the model from the target id returned by `GET_VERSION`.
+    pub fn from_target_id(target_id: u32) -> Self {
+        MODELS
+            .iter()
+            .find(|spec| target_id & 0xffff0000 == spec.target_id_mask)
+            .map(|spec| spec.model)
+            .unwrap_or(Model::Unknown)
+    }
+
+    /// Identify the model from its name on the command line, like `nanos+`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "nanos" => Some(Model::NanoS),
+            "nanos+" | "nanosp" => Some(Model::NanoSP),
+            "nanox" => Some(Model::NanoX),
+            "stax" => Some(Model::Stax),
+            "flex" | "europa" => Some(Model::Flex),
+            _ => None,
+        }
+    }
+
+    /// Identify the model from the USB product id of its HID interface.
+    pub fn from_usb_product_id(product_id: u16) -> Self {
+        MODELS
+            .iter()
+            .find(|spec| {
+                spec.legacy_usb_product_id == product_id
+                    || spec.usb_product_id_mm as u16 == product_id >> 8
+            })
+            .map(|spec| spec.model)
+            .unwrap_or(Model::Unknown)
+    }
+
+    pub fn spec(&self) -> Option<&'static ModelSpec> {
+        MODELS.iter().find(|spec| spec.model == *self)
+    }
+
+    /// Storage available for the firmware and the apps, in bytes.
+    pub fn memory_size(&self) -> Option<u32> {
+        self.spec().map(|spec| spec.memory_size)
+    }
+
+    /// Size of a storage block, the unit of the app sizes reported by the device. It depends on
+    /// the firmware for the Nano S.
+    pub fn block_size(&self, firmware_version: &str) -> Option<u32> {
+        match self {
+            Model::NanoS => {
+                let major = firmware_version
+                    .split('.')
+                    .next()
+                    .and_then(|m| m.parse::<u32>().ok())?;
+                Some(if major < 2 { 4 * 1024 } else { 2 * 1024 })
+            }
+            Model::NanoX => Some(4 * 1024),
+            Model::NanoSP | Model::Stax | Model::Flex => Some(32),
+            Model::Unknown => None,
+        }
+    }
+
+    pub fn screen(&self) -> Option<Screen> {
+        self.spec().map(|spec| spec.screen)
+    }
+}
+
+impl Display for Model {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Model::NanoS => {
+                write!(f, "Nano S")
+            }
+            Model::NanoSP => {
+                write!(f, "Nano S+")
+            }
+            Model::NanoX => {
+                write!(f, "Nano X")
+            }
+            Model::Stax => {
+                write!(f, "Stax")
+            }
+            Model::Flex => {
+                write!(f, "Flex")
+            }
+            Model::Unknown => {
+                write!(f, "Ledger")
+            }
+        }
+    }
+}
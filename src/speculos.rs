@@ -0,0 +1,250 @@
+use ledger_apdu::{APDUAnswer, APDUCommand};
+use serde_derive::Deserialize;
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::transport::{Transport, TransportError};
+
+pub const DEFAULT_APDU_ADDR: &str = "127.0.0.1:9999";
+
+// Delay after which we look whether the emulator is waiting for a user action.
+const AUTO_APPROVE_DELAY: Duration = Duration::from_millis(500);
+
+/// Text starting the screens where both buttons approve what the device asks.
+const CONFIRMATION_TEXTS: [&str; 5] = ["Approve", "Accept", "Allow", "Confirm", "Sign"];
+
+/// The texts displayed by the emulator, as listed by its `/events` endpoint.
+#[derive(Debug, Deserialize)]
+struct ScreenEvents {
+    events: Vec<ScreenEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScreenEvent {
+    #[serde(default)]
+    text: String,
+}
+
+impl ScreenEvents {
+    fn is_confirmation(&self) -> bool {
+        self.events.iter().any(|event| {
+            CONFIRMATION_TEXTS
+                .iter()
+                .any(|text| event.text.trim().starts_with(text))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(unused)]
+pub enum SpeculosButton {
+    Left,
+    Right,
+    Both,
+}
+
+impl SpeculosButton {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SpeculosButton::Left => "left",
+            SpeculosButton::Right => "right",
+            SpeculosButton::Both => "both",
+        }
+    }
+}
+
+/// A transport talking to a [Speculos](https://github.com/LedgerHQ/speculos) emulator over its
+/// TCP APDU port.
+///
+/// The framing is the one used by Speculos' APDU server:
+/// - a command is sent as a 4 bytes big endian length followed by the raw APDU;
+/// - an answer is a 4 bytes big endian length followed by the data and the 2 bytes status word
+///   (which is not accounted in the length).
+pub struct SpeculosTransport {
+    stream: Mutex<TcpStream>,
+    api_url: Option<String>,
+    auto_approve: bool,
+}
+
+impl SpeculosTransport {
+    /// Connect to the APDU port of a running emulator. `api_url` is the base url of the REST
+    /// automation API (usually `http://127.0.0.1:5000`), needed to press buttons.
+    pub fn connect(
+        apdu_addr: &str,
+        api_url: Option<String>,
+        auto_approve: bool,
+    ) -> Result<Self, TransportError> {
+        let stream = TcpStream::connect(apdu_addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream: Mutex::new(stream),
+            api_url,
+            auto_approve,
+        })
+    }
+
+    /// Press a button on the emulated device through the REST automation API.
+    pub fn press_button(&self, button: SpeculosButton) -> Result<(), TransportError> {
+        let request = minreq::Request::new(
+            minreq::Method::Post,
+            self.api_endpoint(&format!("button/{}", button.as_str()))?,
+        )
+        .with_json(&serde_json::json!({ "action": "press-and-release" }));
+        request
+            .and_then(|r| r.send())
+            .map_err(api_error)
+            .and_then(check_status)?;
+        Ok(())
+    }
+
+    /// What the emulated device currently displays.
+    fn current_screen(&self) -> Result<ScreenEvents, TransportError> {
+        let url = self.api_endpoint("events?currentscreenonly=true")?;
+        let resp = minreq::get(url)
+            .send()
+            .map_err(api_error)
+            .and_then(check_status)?;
+        resp.json().map_err(api_error)
+    }
+
+    fn api_endpoint(&self, path: &str) -> Result<String, TransportError> {
+        let api_url = self.api_url.as_ref().ok_or_else(|| {
+            TransportError::Other("Speculos REST API url is not configured.".to_string())
+        })?;
+        Ok(format!("{}/{}", api_url.trim_end_matches('/'), path))
+    }
+
+    /// Block until the emulator starts answering. If auto approval is enabled, both buttons are
+    /// pressed whenever the answer is late and the emulator displays a confirmation.
+    fn wait_answer(&self, stream: &TcpStream) -> Result<(), TransportError> {
+        if !self.auto_approve || self.api_url.is_none() {
+            return Ok(());
+        }
+
+        stream.set_read_timeout(Some(AUTO_APPROVE_DELAY))?;
+        let res = loop {
+            match stream.peek(&mut [0u8; 1]) {
+                Ok(0) => {
+                    break Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+                }
+                Ok(_) => break Ok(()),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    match self.current_screen() {
+                        Ok(screen) if screen.is_confirmation() => {
+                            log::debug!("Speculos is waiting for an user action, approving.");
+                            if let Err(e) = self.press_button(SpeculosButton::Both) {
+                                break Err(e);
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => break Err(e),
+                    }
+                }
+                Err(e) => break Err(e.into()),
+            }
+        };
+        stream.set_read_timeout(None)?;
+        res
+    }
+}
+
+fn api_error(e: minreq::Error) -> TransportError {
+    TransportError::Other(format!("Speculos REST API error: {}", e))
+}
+
+fn check_status(resp: minreq::Response) -> Result<minreq::Response, TransportError> {
+    if !(200..300).contains(&resp.status_code) {
+        return Err(TransportError::Other(format!(
+            "Speculos REST API answered with status {}.",
+            resp.status_code
+        )));
+    }
+    Ok(resp)
+}
+
+impl Transport for SpeculosTransport {
+    fn exchange_apdu(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError> {
+        let mut stream = self
+            .stream
+            .lock()
+            .map_err(|_| TransportError::Other("Speculos stream lock poisoned.".to_string()))?;
+
+        let apdu = command.serialize();
+        stream.write_all(&(apdu.len() as u32).to_be_bytes())?;
+        stream.write_all(&apdu)?;
+
+        self.wait_answer(&stream)?;
+
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len)?;
+        // data + status word
+        let mut answer = vec![0u8; u32::from_be_bytes(len) as usize + 2];
+        stream.read_exact(&mut answer)?;
+
+        APDUAnswer::from_answer(answer).map_err(|_| TransportError::InvalidAnswer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn apdu_framing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let emulator = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0u8; 4];
+            stream.read_exact(&mut len).unwrap();
+            let mut apdu = vec![0u8; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut apdu).unwrap();
+            // The status word is not accounted in the length.
+            stream.write_all(&3u32.to_be_bytes()).unwrap();
+            stream.write_all(&[0x01, 0x02, 0x03, 0x90, 0x00]).unwrap();
+            apdu
+        });
+
+        let transport = SpeculosTransport::connect(&addr, None, false).unwrap();
+        let command = APDUCommand {
+            cla: 0xe0,
+            ins: 0x01,
+            p1: 0,
+            p2: 0,
+            data: &[0xaa, 0xbb][..],
+        };
+        let answer = transport.exchange_apdu(&command).unwrap();
+        assert_eq!(answer.data(), [0x01, 0x02, 0x03]);
+        assert_eq!(answer.retcode(), 0x9000);
+        assert_eq!(
+            emulator.join().unwrap(),
+            [0xe0, 0x01, 0x00, 0x00, 0x02, 0xaa, 0xbb]
+        );
+    }
+
+    #[test]
+    fn confirmation_screens() {
+        let screen = |texts: &[&str]| ScreenEvents {
+            events: texts
+                .iter()
+                .map(|text| ScreenEvent {
+                    text: text.to_string(),
+                })
+                .collect(),
+        };
+        assert!(screen(&["Allow Ledger manager"]).is_confirmation());
+        assert!(screen(&["Approve"]).is_confirmation());
+        assert!(!screen(&["Don't allow"]).is_confirmation());
+        assert!(!screen(&["Bitcoin", "is ready"]).is_confirmation());
+        assert!(!screen(&[]).is_confirmation());
+    }
+}
@@ -12,6 +12,7 @@ use super::color;
 pub enum Theme {
     #[default]
     Dark,
+    #[allow(dead_code)]
     Light,
 }
 
@@ -88,14 +89,20 @@ impl text::StyleSheet for Theme {
 pub enum Container {
     #[default]
     Transparent,
+    #[allow(dead_code)]
     Background,
+    #[allow(dead_code)]
     Foreground,
+    #[allow(dead_code)]
     Border,
     Card(Card),
     Badge(Badge),
     Pill(Pill),
+    #[allow(dead_code)]
     Custom(iced::Color),
+    #[allow(dead_code)]
     Notification(Notification),
+    #[allow(dead_code)]
     QrCode,
 }
 
@@ -209,6 +216,7 @@ impl From<Pill> for Container {
 pub enum Notification {
     #[default]
     Pending,
+    #[allow(dead_code)]
     Error,
 }
 
@@ -267,9 +275,13 @@ impl Notification {
 pub enum Card {
     #[default]
     Simple,
+    #[allow(dead_code)]
     Border,
+    #[allow(dead_code)]
     Invalid,
+    #[allow(dead_code)]
     Warning,
+    #[allow(dead_code)]
     Error,
 }
 
@@ -369,6 +381,7 @@ impl Card {
 pub enum Badge {
     #[default]
     Standard,
+    #[allow(dead_code)]
     Bitcoin,
 }
 
@@ -402,8 +415,11 @@ impl Badge {
 pub enum Pill {
     #[default]
     Simple,
+    #[allow(dead_code)]
     Primary,
+    #[allow(dead_code)]
     Success,
+    #[allow(dead_code)]
     Warning,
 }
 
@@ -518,6 +534,7 @@ impl scrollable::StyleSheet for Theme {
 pub enum PickList {
     #[default]
     Simple,
+    #[allow(dead_code)]
     Invalid,
     Secondary,
 }
@@ -608,12 +625,19 @@ impl checkbox::StyleSheet for Theme {
 pub enum Button {
     #[default]
     Primary,
+    #[allow(dead_code)]
     Secondary,
+    #[allow(dead_code)]
     Destructive,
+    #[allow(dead_code)]
     SecondaryDestructive,
+    #[allow(dead_code)]
     Transparent,
+    #[allow(dead_code)]
     TransparentBorder,
+    #[allow(dead_code)]
     Border,
+    #[allow(dead_code)]
     Menu(bool),
 }
 
@@ -791,6 +815,7 @@ impl button::StyleSheet for Theme {
 pub enum Form {
     #[default]
     Simple,
+    #[allow(dead_code)]
     Invalid,
 }
 
@@ -0,0 +1,254 @@
+use ledger_apdu::{APDUAnswer, APDUCommand};
+use serde_derive::{Deserialize, Serialize};
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::transport::{Transport, TransportError};
+
+/// A recorded APDU exchange. Byte strings are hex encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedExchange {
+    pub command: String,
+    pub response: String,
+    pub status_word: u16,
+    pub elapsed_ms: u64,
+    /// Set if the transport itself failed, in which case there is no response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An ordered list of APDU exchanges. On disk, either a JSON object holding the `exchanges` array
+/// or one JSON exchange per line, as written by the [`Recorder`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    pub exchanges: Vec<RecordedExchange>,
+}
+
+impl Transcript {
+    pub fn load(path: &Path) -> Result<Self, TransportError> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|e| {
+            TransportError::Other(format!("Invalid transcript {}: {}", path.display(), e))
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<Self, serde_json::Error> {
+        if let Ok(transcript) = serde_json::from_str(content) {
+            return Ok(transcript);
+        }
+        let exchanges = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { exchanges })
+    }
+}
+
+/// Collects the exchanges of every transport wrapped in a [`RecordingTransport`] into a single
+/// transcript. Each exchange is appended to the file as a JSON line as soon as it is done, so
+/// nothing is lost if the app is killed.
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Create the transcript file, truncating it if it exists.
+    pub fn new(path: &Path) -> Result<Self, TransportError> {
+        Ok(Self {
+            file: Mutex::new(File::create(path)?),
+        })
+    }
+
+    fn record(&self, exchange: RecordedExchange) {
+        let mut file = match self.file.lock() {
+            Ok(f) => f,
+            Err(_) => return,
+        };
+        let mut line = match serde_json::to_string(&exchange) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Fail to serialize APDU exchange: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            log::error!("Fail to write transcript: {}", e);
+        }
+    }
+}
+
+/// A transport wrapper recording every APDU exchanged with the inner transport.
+pub struct RecordingTransport<T> {
+    inner: T,
+    recorder: Arc<Recorder>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new(inner: T, recorder: Arc<Recorder>) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn exchange_apdu(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError> {
+        let start = Instant::now();
+        let res = self.inner.exchange_apdu(command);
+        let elapsed_ms = start.elapsed().as_millis() as u64;
+
+        let command = hex::encode(command.serialize());
+        let exchange = match &res {
+            Ok(answer) => RecordedExchange {
+                command,
+                response: hex::encode(answer.data()),
+                status_word: answer.retcode(),
+                elapsed_ms,
+                error: None,
+            },
+            Err(e) => RecordedExchange {
+                command,
+                response: String::new(),
+                status_word: 0,
+                elapsed_ms,
+                error: Some(e.to_string()),
+            },
+        };
+        self.recorder.record(exchange);
+
+        res
+    }
+}
+
+/// A transport serving back a recorded transcript. Commands must be sent in the same order as
+/// they were recorded.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    exchanges: Vec<RecordedExchange>,
+    cursor: Mutex<usize>,
+}
+
+impl ReplayTransport {
+    pub fn new(transcript: Transcript) -> Self {
+        Self {
+            exchanges: transcript.exchanges,
+            cursor: Mutex::new(0),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, TransportError> {
+        Transcript::load(path).map(Self::new)
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn exchange_apdu(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError> {
+        let mut cursor = self
+            .cursor
+            .lock()
+            .map_err(|_| TransportError::Other("Replay cursor lock poisoned.".to_string()))?;
+        let expected = self
+            .exchanges
+            .get(*cursor)
+            .ok_or_else(|| TransportError::Other("End of transcript reached.".to_string()))?;
+
+        let command = hex::encode(command.serialize());
+        if command != expected.command {
+            return Err(TransportError::Other(format!(
+                "Unexpected APDU {}, transcript expects {} at position {}.",
+                command, expected.command, *cursor
+            )));
+        }
+        *cursor += 1;
+
+        if let Some(e) = &expected.error {
+            return Err(TransportError::Other(e.clone()));
+        }
+        let mut answer = hex::decode(&expected.response)
+            .map_err(|e| TransportError::Other(format!("Invalid recorded response: {}", e)))?;
+        answer.extend_from_slice(&expected.status_word.to_be_bytes());
+        APDUAnswer::from_answer(answer).map_err(|_| TransportError::InvalidAnswer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger_lib::{get_app_and_version, list_installed_apps, DeviceInfo};
+    use crate::model::Model;
+
+    /// A Nano X on firmware 2.2.3 polled on its dashboard, holding Bitcoin, Bitcoin Test and
+    /// Ethereum. Not recorded from a device: it is built from the answers the parsers are tested
+    /// with, in the order `DeviceContext::poll` sends the commands.
+    const NANO_X_DASHBOARD: &str =
+        include_str!("../fixtures/nanox-2.2.3-dashboard.synthetic.jsonl");
+
+    fn dashboard() -> Transcript {
+        Transcript::parse(NANO_X_DASHBOARD).unwrap()
+    }
+
+    #[test]
+    fn replay_dashboard() {
+        let transport = ReplayTransport::new(dashboard());
+
+        let info = DeviceInfo::new(&transport).unwrap();
+        assert_eq!(info.model(), Model::NanoX);
+        assert_eq!(info.version, "2.2.3");
+        assert!(info.status.onboarded);
+
+        let names: Vec<_> = list_installed_apps(&transport)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.name)
+            .collect();
+        assert_eq!(names, ["Bitcoin", "Bitcoin Test", "Ethereum"]);
+
+        // The transcript is over.
+        assert!(DeviceInfo::new(&transport).is_err());
+    }
+
+    #[test]
+    fn replay_rejects_other_commands() {
+        let transport = ReplayTransport::new(dashboard());
+        assert!(list_installed_apps(&transport).is_err());
+    }
+
+    #[test]
+    fn record_then_replay() {
+        let path =
+            std::env::temp_dir().join(format!("bacca-transcript-{}.jsonl", std::process::id()));
+        let recorder = Arc::new(Recorder::new(&path).unwrap());
+        let transport = RecordingTransport::new(ReplayTransport::new(dashboard()), recorder);
+        DeviceInfo::new(&transport).unwrap();
+        list_installed_apps(&transport).unwrap();
+        // Failed exchanges are recorded too.
+        assert!(get_app_and_version(&transport).is_err());
+
+        let recorded = Transcript::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(recorded.exchanges.len(), dashboard().exchanges.len() + 1);
+        for (recorded, expected) in recorded.exchanges.iter().zip(dashboard().exchanges) {
+            assert_eq!(recorded.command, expected.command);
+            assert_eq!(recorded.response, expected.response);
+            assert_eq!(recorded.status_word, expected.status_word);
+        }
+        assert!(recorded.exchanges.last().unwrap().error.is_some());
+
+        // The JSON object form is still accepted.
+        let object = serde_json::to_string(&dashboard()).unwrap();
+        assert_eq!(Transcript::parse(&object).unwrap().exchanges.len(), 4);
+    }
+}
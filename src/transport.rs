@@ -0,0 +1,249 @@
+use ledger_apdu::{APDUAnswer, APDUCommand};
+use ledger_transport_hidapi::{hidapi::HidError, LedgerHIDError, TransportNativeHID};
+
+use std::{error, fmt, future::Future, io, ops::Deref, sync::Arc};
+
+/// What made the HID backend fail, as far as it can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HidErrorKind {
+    /// The device can't be opened by this user, like without the udev rules on Linux.
+    PermissionDenied,
+    Other,
+}
+
+impl From<io::ErrorKind> for HidErrorKind {
+    fn from(kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::PermissionDenied => HidErrorKind::PermissionDenied,
+            _ => HidErrorKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TransportError {
+    /// The HID backend failed to open or exchange with the device.
+    Hid(HidErrorKind, String),
+    /// An I/O error occurred on the underlying channel (socket, file...).
+    Io(Arc<std::io::Error>),
+    /// The device answer is too short to carry a status word.
+    InvalidAnswer,
+    /// Any other backend specific failure.
+    Other(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Hid(_, e) => write!(f, "HID error: {}", e),
+            TransportError::Io(e) => write!(f, "I/O error: {}", e),
+            TransportError::InvalidAnswer => write!(f, "Invalid APDU answer"),
+            TransportError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for TransportError {}
+
+impl PartialEq for TransportError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TransportError::Hid(a, c), TransportError::Hid(b, d)) => a == b && c == d,
+            (TransportError::Other(a), TransportError::Other(b)) => a == b,
+            (TransportError::Io(a), TransportError::Io(b)) => a.kind() == b.kind(),
+            (TransportError::InvalidAnswer, TransportError::InvalidAnswer) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<std::io::Error> for TransportError {
+    fn from(e: std::io::Error) -> Self {
+        TransportError::Io(Arc::new(e))
+    }
+}
+
+impl From<LedgerHIDError> for TransportError {
+    fn from(e: LedgerHIDError) -> Self {
+        let kind = match &e {
+            LedgerHIDError::Io(e) => e.kind().into(),
+            LedgerHIDError::Hid(e) => HidErrorKind::from(e),
+            _ => HidErrorKind::Other,
+        };
+        TransportError::Hid(kind, e.to_string())
+    }
+}
+
+impl From<&HidError> for HidErrorKind {
+    fn from(e: &HidError) -> Self {
+        match e {
+            // hidapi's hidraw backend, the one used on Linux, reports a failed open with the bare
+            // `strerror` of the OS error. The other backends don't tell why.
+            HidError::HidApiError { message }
+                if cfg!(target_os = "linux")
+                    && [EPERM, EACCES]
+                        .iter()
+                        .any(|code| *message == strerror(*code)) =>
+            {
+                HidErrorKind::PermissionDenied
+            }
+            _ => HidErrorKind::Other,
+        }
+    }
+}
+
+const EPERM: i32 = 1;
+const EACCES: i32 = 13;
+
+/// The message of an OS error, as given by the C library.
+fn strerror(code: i32) -> String {
+    let message = io::Error::from_raw_os_error(code).to_string();
+    // Rust adds the code to it.
+    match message.rfind(" (os error ") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+/// A blocking channel able to exchange APDUs with a Ledger device (or anything pretending to be
+/// one). All the functions in `ledger_lib` go through this trait, the HID transport being only
+/// one backend among others.
+pub trait Transport: Send + Sync {
+    fn exchange_apdu(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn exchange_apdu(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError> {
+        (**self).exchange_apdu(command)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn exchange_apdu(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError> {
+        (**self).exchange_apdu(command)
+    }
+}
+
+/// Helper to send any kind of `APDUCommand` through a [`Transport`].
+pub trait Exchange {
+    fn exchange<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError>;
+}
+
+impl<T: Transport + ?Sized> Exchange for T {
+    fn exchange<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError> {
+        self.exchange_apdu(&APDUCommand {
+            cla: command.cla,
+            ins: command.ins,
+            p1: command.p1,
+            p2: command.p2,
+            data: command.data.deref(),
+        })
+    }
+}
+
+/// Async flavor of [`Exchange`], for callers living on the tokio runtime.
+pub trait AsyncExchange {
+    fn exchange_async(
+        &self,
+        command: APDUCommand<Vec<u8>>,
+    ) -> impl Future<Output = Result<APDUAnswer<Vec<u8>>, TransportError>> + Send;
+}
+
+/// A shared blocking transport is run on the tokio blocking thread pool, so it never stalls the
+/// runtime while waiting for the device.
+impl<T: Transport + ?Sized + 'static> AsyncExchange for Arc<T> {
+    fn exchange_async(
+        &self,
+        command: APDUCommand<Vec<u8>>,
+    ) -> impl Future<Output = Result<APDUAnswer<Vec<u8>>, TransportError>> + Send {
+        let transport = self.clone();
+        async move {
+            tokio::task::spawn_blocking(move || transport.exchange(&command))
+                .await
+                .map_err(|e| TransportError::Other(format!("Exchange task failed: {}", e)))?
+        }
+    }
+}
+
+impl Transport for TransportNativeHID {
+    fn exchange_apdu(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, TransportError> {
+        TransportNativeHID::exchange(self, command).map_err(TransportError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LedgerError;
+
+    #[test]
+    fn hid_error_kind() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let e = TransportError::from(LedgerHIDError::Io(denied));
+        assert!(matches!(
+            e,
+            TransportError::Hid(HidErrorKind::PermissionDenied, _)
+        ));
+        assert!(matches!(
+            LedgerError::from(e),
+            LedgerError::PermissionDenied(_)
+        ));
+
+        // Only the kind tells, not the message.
+        let e = TransportError::from(LedgerHIDError::Comm("permission"));
+        assert!(matches!(e, TransportError::Hid(HidErrorKind::Other, _)));
+        assert!(matches!(LedgerError::from(e), LedgerError::Hid(_)));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn hid_open_error_kind() {
+        // What hidraw reports when the udev rules are missing.
+        let open = |message: &str| {
+            TransportError::from(LedgerHIDError::Hid(HidError::HidApiError {
+                message: message.to_string(),
+            }))
+        };
+        assert_eq!(strerror(EACCES), "Permission denied");
+        let e = open("Permission denied");
+        assert!(matches!(
+            e,
+            TransportError::Hid(HidErrorKind::PermissionDenied, _)
+        ));
+        assert!(matches!(
+            LedgerError::from(e),
+            LedgerError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            open("Operation not permitted"),
+            TransportError::Hid(HidErrorKind::PermissionDenied, _)
+        ));
+
+        for message in ["No such device", "Permission denied on the moon"] {
+            assert!(matches!(
+                open(message),
+                TransportError::Hid(HidErrorKind::Other, _)
+            ));
+        }
+        let e = TransportError::from(LedgerHIDError::Hid(HidError::HidApiErrorEmpty));
+        assert!(matches!(e, TransportError::Hid(HidErrorKind::Other, _)));
+    }
+}